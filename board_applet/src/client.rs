@@ -0,0 +1,464 @@
+// Written by sigroot
+//! sig_rp2040_applet - clients for sending commands to a running board
+//!
+//! BoardClient is asynchronous (tokio, requires the 'client' feature) and
+//! BlockingBoardClient uses std networking for simple tools. Both serialize
+//! Command structures and decode the board's response codes.
+
+use crate::{
+    Allocation, Command, HelloResponse, Opcode, Response, ResponseCode, ResponseFormat, Separator,
+};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Port the board listens on by default
+pub const DEFAULT_PORT: u16 = 27072;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The board responded with an error code
+    Response(ResponseCode),
+    /// An update was sent before an applet was created
+    NoApplet,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "Board connection failed: {e}"),
+            ClientError::Json(e) => write!(f, "Invalid JSON: {e}"),
+            ClientError::Response(x) => write!(f, "Board returned error {x}"),
+            ClientError::NoApplet => write!(f, "No applet has been created"),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+/// The applet a client created and the commands that modify it
+#[derive(Default)]
+struct AppletState {
+    screen: u8,
+    app_num: Option<u8>,
+    session: Option<u64>,
+    format: ResponseFormat,
+}
+
+impl AppletState {
+    fn create_command(&self, slot: u8, separator: Separator) -> Command {
+        let mut command = Command::create(slot, separator);
+        command.screen = self.screen;
+        command
+    }
+
+    fn resume_command(&self, slot: u8, token: u64) -> Command {
+        let mut command = Command::new(Opcode::Resume, slot, token.to_be_bytes().to_vec());
+        command.screen = self.screen;
+        command
+    }
+
+    fn update_command(&self, opcode: Opcode, parameters: Vec<u8>) -> Result<Command, ClientError> {
+        let mut command = Command::new(
+            opcode,
+            self.app_num.ok_or(ClientError::NoApplet)?,
+            parameters,
+        );
+        command.screen = self.screen;
+        Ok(command)
+    }
+
+    /// Serializes a command, switching the response format when it requests
+    /// one
+    fn encode(&mut self, command: &Command) -> Result<Vec<u8>, ClientError> {
+        if let Some(x) = command.response {
+            self.format = x;
+        }
+        Ok(serde_json::to_vec(command)?)
+    }
+
+    /// Result of a no-ack command (None if a response must be read)
+    fn skip_response(&mut self, command: &Command) -> Option<ResponseCode> {
+        if command.awaits_response() {
+            return None;
+        }
+        self.update(command, &Response::new(0, None));
+        Some(ResponseCode::Success)
+    }
+
+    /// Checks a response, remembering the applet a successful command created
+    /// or resumed
+    fn finish(
+        &mut self,
+        command: &Command,
+        response: &Response,
+    ) -> Result<ResponseCode, ClientError> {
+        let code = check_response(response.code)?;
+        self.update(command, response);
+        Ok(code)
+    }
+
+    /// Decodes a Hello response line and switches to the encoding it chose
+    fn hello(&mut self, line: &[u8]) -> Result<HelloResponse, ClientError> {
+        let hello: HelloResponse = serde_json::from_slice(line)?;
+        check_response(hello.code)?;
+        self.format = hello.encoding;
+        Ok(hello)
+    }
+
+    /// Remembers the applet a successful command created or resumed
+    fn update(&mut self, command: &Command, response: &Response) {
+        match command.opcode {
+            Opcode::CreateApplet => {
+                self.app_num = Some(response.app_num.unwrap_or(command.app_num));
+                self.session = response.session;
+            }
+            Opcode::Resume => self.app_num = Some(command.app_num),
+            _ => (),
+        }
+    }
+}
+
+/// Converts a response byte to a result (covered applets are not errors)
+fn check_response(value: u8) -> Result<ResponseCode, ClientError> {
+    let code = ResponseCode::from_u8(value);
+    match code.is_success() {
+        true => Ok(code),
+        false => Err(ClientError::Response(code)),
+    }
+}
+
+/// Number of bytes following a response byte (the chosen slot, then the
+/// session token of a successful CreateApplet)
+fn extra_bytes(command: &Command, code: u8) -> usize {
+    if command.opcode != Opcode::CreateApplet || !ResponseCode::from_u8(code).is_success() {
+        return 0;
+    }
+    command.allocates() as usize + 8 * command.session as usize
+}
+
+/// Decodes a response byte and the bytes following it
+fn decode_bytes(command: &Command, code: u8, extra: &[u8]) -> Response {
+    let mut response = Response::new(code, None);
+    if extra.is_empty() {
+        return response;
+    }
+    let (slot, token) = extra.split_at(command.allocates() as usize);
+    response.app_num = slot.first().copied();
+    response.session = token.try_into().ok().map(u64::from_be_bytes);
+    response
+}
+
+/// Removes the newline ending a JSON response (Err if the stream ended first)
+fn end_line(mut line: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+    match line.pop() {
+        Some(b'\n') => Ok(line),
+        _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+}
+
+/// Whether a JSON line is a notice the board sent without a command
+fn is_notice(line: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(line).is_ok_and(|x| x["notice"] == true)
+}
+
+/// Flattens a 9x10 grid to UpdateGrid parameters (rows then columns)
+fn grid_parameters(grid: &[[u8; 9]; 10]) -> Vec<u8> {
+    grid.iter().flatten().copied().collect()
+}
+
+/// Asynchronous client for the applets of one stream on a running board
+#[cfg(feature = "client")]
+pub struct BoardClient {
+    stream: tokio::io::BufReader<tokio::net::TcpStream>,
+    state: AppletState,
+}
+
+#[cfg(feature = "client")]
+impl BoardClient {
+    /// Connects to a board (e.g. "127.0.0.1:27072")
+    pub async fn connect<A: tokio::net::ToSocketAddrs>(addr: A) -> Result<Self, ClientError> {
+        Ok(BoardClient {
+            stream: tokio::io::BufReader::new(tokio::net::TcpStream::connect(addr).await?),
+            state: AppletState::default(),
+        })
+    }
+
+    /// Sets the screen used by following commands (default 0)
+    pub fn set_screen(&mut self, screen: u8) {
+        self.state.screen = screen;
+    }
+
+    /// Selects which of the stream's applets following updates go to (the
+    /// last created or resumed applet by default)
+    pub fn set_applet(&mut self, slot: u8) {
+        self.state.app_num = Some(slot);
+    }
+
+    /// Session token of the created applet (if created with a session)
+    pub fn session(&self) -> Option<u64> {
+        self.state.session
+    }
+
+    /// Creates an applet in a slot (0 is the status bar, 1-3 are grids)
+    pub async fn create_applet(
+        &mut self,
+        slot: u8,
+        separator: Separator,
+    ) -> Result<ResponseCode, ClientError> {
+        let command = self.state.create_command(slot, separator);
+        self.send(&command).await
+    }
+
+    /// Creates an applet in a slot the board chooses, returning the slot
+    pub async fn allocate_applet(
+        &mut self,
+        allocation: Allocation,
+        slot: u8,
+        separator: Separator,
+    ) -> Result<u8, ClientError> {
+        let mut command = self.state.create_command(slot, separator);
+        command.allocate = allocation;
+        self.send(&command).await?;
+        self.state.app_num.ok_or(ClientError::NoApplet)
+    }
+
+    /// Reattaches to an applet created with a session before reconnecting
+    pub async fn resume(&mut self, slot: u8, token: u64) -> Result<ResponseCode, ClientError> {
+        let command = self.state.resume_command(slot, token);
+        self.send(&command).await
+    }
+
+    /// Rewrites the selected applet's 9x10 grid
+    pub async fn update_grid(&mut self, grid: &[[u8; 9]; 10]) -> Result<ResponseCode, ClientError> {
+        let command = self
+            .state
+            .update_command(Opcode::UpdateGrid, grid_parameters(grid))?;
+        self.send(&command).await
+    }
+
+    /// Rewrites the selected applet's 9x1 separator (must be variable)
+    pub async fn update_bar(&mut self, bar: &[u8; 9]) -> Result<ResponseCode, ClientError> {
+        let command = self.state.update_command(Opcode::UpdateBar, bar.to_vec())?;
+        self.send(&command).await
+    }
+
+    /// Fills the selected applet's progress bar separator to a 0-100 value
+    pub async fn update_progress(&mut self, value: u8) -> Result<ResponseCode, ClientError> {
+        let command = self.state.update_command(Opcode::UpdateBar, vec![value])?;
+        self.send(&command).await
+    }
+
+    /// Changes the selected applet's separator style
+    pub async fn set_separator_style(
+        &mut self,
+        separator: Separator,
+    ) -> Result<ResponseCode, ClientError> {
+        let mut command = self
+            .state
+            .update_command(Opcode::SetSeparatorStyle, Vec::new())?;
+        command.separator = Some(separator);
+        self.send(&command).await
+    }
+
+    /// Sends any command and waits for its response code (no-ack commands
+    /// return Success without waiting, the board does not report their
+    /// errors)
+    pub async fn send(&mut self, command: &Command) -> Result<ResponseCode, ClientError> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let request = self.state.encode(command)?;
+        self.stream.write_all(&request).await?;
+        if let Some(code) = self.state.skip_response(command) {
+            return Ok(code);
+        }
+        let response = match self.state.format {
+            ResponseFormat::Byte => {
+                let code = self.stream.read_u8().await?;
+                let mut extra = vec![0; extra_bytes(command, code)];
+                self.stream.read_exact(&mut extra).await?;
+                decode_bytes(command, code, &extra)
+            }
+            ResponseFormat::Json => serde_json::from_slice(&self.read_line().await?)?,
+        };
+        self.state.finish(command, &response)
+    }
+
+    /// Checks the board supports this protocol version, asks for an encoding
+    /// of following responses and describes the board
+    pub async fn hello(&mut self, encoding: ResponseFormat) -> Result<HelloResponse, ClientError> {
+        use tokio::io::AsyncWriteExt;
+
+        let request = self.state.encode(&Command::hello_with(encoding))?;
+        self.stream.write_all(&request).await?;
+        let line = self.read_line().await?;
+        self.state.hello(&line)
+    }
+
+    /// Reads one line of a JSON response (skipping notices)
+    async fn read_line(&mut self) -> Result<Vec<u8>, ClientError> {
+        use tokio::io::AsyncBufReadExt;
+
+        loop {
+            let mut line = Vec::new();
+            self.stream.read_until(b'\n', &mut line).await?;
+            let line = end_line(line)?;
+            if !is_notice(&line) {
+                return Ok(line);
+            }
+        }
+    }
+}
+
+/// Blocking client for the applets of one stream on a running board
+pub struct BlockingBoardClient {
+    stream: BufReader<TcpStream>,
+    state: AppletState,
+}
+
+impl BlockingBoardClient {
+    /// Connects to a board (e.g. "127.0.0.1:27072")
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ClientError> {
+        Ok(BlockingBoardClient {
+            stream: BufReader::new(TcpStream::connect(addr)?),
+            state: AppletState::default(),
+        })
+    }
+
+    /// Sets the screen used by following commands (default 0)
+    pub fn set_screen(&mut self, screen: u8) {
+        self.state.screen = screen;
+    }
+
+    /// Selects which of the stream's applets following updates go to (the
+    /// last created or resumed applet by default)
+    pub fn set_applet(&mut self, slot: u8) {
+        self.state.app_num = Some(slot);
+    }
+
+    /// Session token of the created applet (if created with a session)
+    pub fn session(&self) -> Option<u64> {
+        self.state.session
+    }
+
+    /// Creates an applet in a slot (0 is the status bar, 1-3 are grids)
+    pub fn create_applet(
+        &mut self,
+        slot: u8,
+        separator: Separator,
+    ) -> Result<ResponseCode, ClientError> {
+        let command = self.state.create_command(slot, separator);
+        self.send(&command)
+    }
+
+    /// Creates an applet in a slot the board chooses, returning the slot
+    pub fn allocate_applet(
+        &mut self,
+        allocation: Allocation,
+        slot: u8,
+        separator: Separator,
+    ) -> Result<u8, ClientError> {
+        let mut command = self.state.create_command(slot, separator);
+        command.allocate = allocation;
+        self.send(&command)?;
+        self.state.app_num.ok_or(ClientError::NoApplet)
+    }
+
+    /// Reattaches to an applet created with a session before reconnecting
+    pub fn resume(&mut self, slot: u8, token: u64) -> Result<ResponseCode, ClientError> {
+        let command = self.state.resume_command(slot, token);
+        self.send(&command)
+    }
+
+    /// Rewrites the selected applet's 9x10 grid
+    pub fn update_grid(&mut self, grid: &[[u8; 9]; 10]) -> Result<ResponseCode, ClientError> {
+        let command = self
+            .state
+            .update_command(Opcode::UpdateGrid, grid_parameters(grid))?;
+        self.send(&command)
+    }
+
+    /// Rewrites the selected applet's 9x1 separator (must be variable)
+    pub fn update_bar(&mut self, bar: &[u8; 9]) -> Result<ResponseCode, ClientError> {
+        let command = self.state.update_command(Opcode::UpdateBar, bar.to_vec())?;
+        self.send(&command)
+    }
+
+    /// Fills the selected applet's progress bar separator to a 0-100 value
+    pub fn update_progress(&mut self, value: u8) -> Result<ResponseCode, ClientError> {
+        let command = self.state.update_command(Opcode::UpdateBar, vec![value])?;
+        self.send(&command)
+    }
+
+    /// Changes the selected applet's separator style
+    pub fn set_separator_style(
+        &mut self,
+        separator: Separator,
+    ) -> Result<ResponseCode, ClientError> {
+        let mut command = self
+            .state
+            .update_command(Opcode::SetSeparatorStyle, Vec::new())?;
+        command.separator = Some(separator);
+        self.send(&command)
+    }
+
+    /// Sends any command and waits for its response code (no-ack commands
+    /// return Success without waiting, the board does not report their
+    /// errors)
+    pub fn send(&mut self, command: &Command) -> Result<ResponseCode, ClientError> {
+        let request = self.state.encode(command)?;
+        self.stream.get_mut().write_all(&request)?;
+        if let Some(code) = self.state.skip_response(command) {
+            return Ok(code);
+        }
+        let response = match self.state.format {
+            ResponseFormat::Byte => {
+                let mut code = [0; 1];
+                self.stream.read_exact(&mut code)?;
+                let mut extra = vec![0; extra_bytes(command, code[0])];
+                self.stream.read_exact(&mut extra)?;
+                decode_bytes(command, code[0], &extra)
+            }
+            ResponseFormat::Json => serde_json::from_slice(&self.read_line()?)?,
+        };
+        self.state.finish(command, &response)
+    }
+
+    /// Checks the board supports this protocol version, asks for an encoding
+    /// of following responses and describes the board
+    pub fn hello(&mut self, encoding: ResponseFormat) -> Result<HelloResponse, ClientError> {
+        let request = self.state.encode(&Command::hello_with(encoding))?;
+        self.stream.get_mut().write_all(&request)?;
+        let line = self.read_line()?;
+        self.state.hello(&line)
+    }
+
+    /// Reads one line of a JSON response (skipping notices)
+    fn read_line(&mut self) -> Result<Vec<u8>, ClientError> {
+        loop {
+            let mut line = Vec::new();
+            self.stream.read_until(b'\n', &mut line)?;
+            let line = end_line(line)?;
+            if !is_notice(&line) {
+                return Ok(line);
+            }
+        }
+    }
+}
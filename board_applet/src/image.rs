@@ -0,0 +1,408 @@
+// Written by sigroot
+//! sig_rp2040_applet - converts images to applet grids
+//!
+//! Netpbm images (PBM, PGM and PPM) are always supported and PNG images require
+//! the 'png' feature. Images are converted to grayscale, then scaled to a 9x10
+//! applet grid or the whole 9x34 matrix.
+//!
+//! Dithering reduces brightnesses to a few evenly spaced levels, since the
+//! lowest PWM levels are hard to tell apart at an angle.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// The data is not an image format that can be read
+    UnknownFormat,
+    /// The image's header or pixel data is damaged
+    Invalid(String),
+    #[cfg(feature = "png")]
+    Png(png::DecodingError),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::UnknownFormat => write!(f, "Unknown image format"),
+            ImageError::Invalid(x) => write!(f, "Invalid image: {x}"),
+            #[cfg(feature = "png")]
+            ImageError::Png(e) => write!(f, "Invalid PNG image: {e}"),
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+#[cfg(feature = "png")]
+impl From<png::DecodingError> for ImageError {
+    fn from(value: png::DecodingError) -> Self {
+        ImageError::Png(value)
+    }
+}
+
+/// How an image is fitted to a different aspect ratio
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fit {
+    /// Scale each side independently
+    #[default]
+    Stretch,
+    /// Scale to cover the whole area and cut off the edges that do not fit
+    Crop,
+    /// Scale to fit inside the area and leave the rest off
+    Contain,
+}
+
+/// How grayscale brightnesses are turned into LED values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tone {
+    /// Keep every brightness
+    #[default]
+    Gray,
+    /// Turn LEDs at or above a brightness fully on and the rest off
+    Threshold(u8),
+    /// Reduce brightnesses to a number of levels (2-255) with a dither
+    Dither(Dither, u8),
+}
+
+/// How brightnesses between two levels are spread out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Round to the nearest level
+    #[default]
+    None,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer,
+    /// Error diffusion to the following pixels (Floyd–Steinberg)
+    FloydSteinberg,
+    /// Switch each pixel between its nearest levels from frame to frame (a
+    /// still image only gets one frame's pattern)
+    Temporal,
+}
+
+/// 4x4 Bayer matrix, thresholds 0-15
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduces rows of brightnesses to evenly spaced levels (at least 2), spreading
+/// the difference with a dither
+///
+/// The frame number moves the pattern of temporal dithering, so over 16 frames
+/// each pixel averages to its brightness
+pub fn dither(pixels: &mut [u8], width: usize, levels: u8, dither: Dither, frame: u64) {
+    let step = 255.0 / (levels.max(2) - 1) as f32;
+    let width = width.max(1);
+    let mut errors = vec![0.0; pixels.len() + width + 1];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let position = (*pixel as f32 + errors[i]).clamp(0.0, 255.0) / step;
+        let lower = position.floor();
+        // Take the upper level when the fraction passes the threshold
+        let threshold = match dither {
+            Dither::None | Dither::FloydSteinberg => 0.5,
+            Dither::Bayer => (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0,
+            Dither::Temporal => {
+                let index = (frame as usize + x * 5 + y * 3) % 16;
+                (BAYER_4X4[index / 4][index % 4] as f32 + 0.5) / 16.0
+            }
+        };
+        let level = match position - lower > threshold {
+            true => lower + 1.0,
+            false => lower,
+        };
+        let value = (level * step).round().min(255.0);
+        if dither == Dither::FloydSteinberg {
+            // 7/16 right, 3/16 below left, 5/16 below, 1/16 below right
+            let error = *pixel as f32 + errors[i] - value;
+            if x + 1 < width {
+                errors[i + 1] += error * 7.0 / 16.0;
+                errors[i + width + 1] += error / 16.0;
+            }
+            if x > 0 {
+                errors[i + width - 1] += error * 3.0 / 16.0;
+            }
+            errors[i + width] += error * 5.0 / 16.0;
+        }
+        *pixel = value as u8;
+    }
+}
+
+/// Grayscale image, one brightness per pixel in rows from the top left
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Reads a PBM, PGM, PPM or (with the 'png' feature) PNG image
+    pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+        match data {
+            [b'P', b'1'..=b'6', ..] => decode_netpbm(data),
+            [0x89, b'P', b'N', b'G', ..] => decode_png(data),
+            _ => Err(ImageError::UnknownFormat),
+        }
+    }
+
+    /// Brightness of a pixel
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Scales the image to a new size, averaging the pixels that fall into
+    /// each new pixel (an empty image or size gives a blank image)
+    pub fn resize(&self, width: usize, height: usize, fit: Fit) -> Image {
+        if self.width == 0 || self.height == 0 || width == 0 || height == 0 {
+            return Image {
+                width,
+                height,
+                pixels: vec![0; width * height],
+            };
+        }
+        match fit {
+            Fit::Stretch => self.scale(0, 0, self.width, self.height, width, height),
+            Fit::Crop => {
+                // Use the largest centered area with the new aspect ratio
+                let (crop_width, crop_height) = match self.width * height > width * self.height {
+                    true => ((self.height * width / height).max(1), self.height),
+                    false => (self.width, (self.width * height / width).max(1)),
+                };
+                self.scale(
+                    (self.width - crop_width) / 2,
+                    (self.height - crop_height) / 2,
+                    crop_width,
+                    crop_height,
+                    width,
+                    height,
+                )
+            }
+            Fit::Contain => {
+                // Scale to the largest size that fits, then center it
+                let (inner_width, inner_height) = match self.width * height > width * self.height {
+                    true => (width, (self.height * width / self.width).clamp(1, height)),
+                    false => ((self.width * height / self.height).clamp(1, width), height),
+                };
+                let inner = self.resize(inner_width, inner_height, Fit::Stretch);
+                let (left, top) = ((width - inner_width) / 2, (height - inner_height) / 2);
+                let mut pixels = vec![0; width * height];
+                for y in 0..inner_height {
+                    let row = (top + y) * width + left;
+                    pixels[row..row + inner_width]
+                        .copy_from_slice(&inner.pixels[y * inner_width..(y + 1) * inner_width]);
+                }
+                Image {
+                    width,
+                    height,
+                    pixels,
+                }
+            }
+        }
+    }
+
+    /// Scales an area of the image (left, top, width, height) to a new size
+    fn scale(
+        &self,
+        left: usize,
+        top: usize,
+        area_width: usize,
+        area_height: usize,
+        width: usize,
+        height: usize,
+    ) -> Image {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            // Each new pixel covers at least one old pixel when enlarging
+            let y_start = top + y * area_height / height;
+            let y_end = (top + (y + 1) * area_height / height).max(y_start + 1);
+            for x in 0..width {
+                let x_start = left + x * area_width / width;
+                let x_end = (left + (x + 1) * area_width / width).max(x_start + 1);
+                let mut sum = 0;
+                for row in y_start..y_end {
+                    for column in x_start..x_end {
+                        sum += self.pixel(column, row) as usize;
+                    }
+                }
+                pixels.push((sum / ((y_end - y_start) * (x_end - x_start))) as u8);
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Applies a tone to every pixel
+    pub fn apply_tone(&mut self, tone: Tone) {
+        match tone {
+            Tone::Gray => (),
+            Tone::Threshold(level) => {
+                for pixel in self.pixels.iter_mut() {
+                    *pixel = if *pixel >= level { 255 } else { 0 };
+                }
+            }
+            Tone::Dither(x, levels) => dither(&mut self.pixels, self.width, levels, x, 0),
+        }
+    }
+
+    /// Converts the image to a 9x10 applet grid
+    pub fn to_grid(&self, fit: Fit, tone: Tone) -> [[u8; 9]; 10] {
+        let mut image = self.resize(9, 10, fit);
+        image.apply_tone(tone);
+        let mut grid = [[0; 9]; 10];
+        for (row, pixels) in grid.iter_mut().zip(image.pixels.chunks(9)) {
+            row.copy_from_slice(pixels);
+        }
+        grid
+    }
+
+    /// Converts the image to the whole 9x34 matrix (the status bar, then each
+    /// applet's separator and grid)
+    pub fn to_matrix(&self, fit: Fit, tone: Tone) -> [[u8; 9]; 34] {
+        let mut image = self.resize(9, 34, fit);
+        image.apply_tone(tone);
+        let mut matrix = [[0; 9]; 34];
+        for (row, pixels) in matrix.iter_mut().zip(image.pixels.chunks(9)) {
+            row.copy_from_slice(pixels);
+        }
+        matrix
+    }
+}
+
+/// Converts red, green and blue to a brightness
+fn luma(red: u8, green: u8, blue: u8) -> u8 {
+    ((red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000) as u8
+}
+
+/// Reads a PBM (P1/P4), PGM (P2/P5) or PPM (P3/P6) image
+fn decode_netpbm(data: &[u8]) -> Result<Image, ImageError> {
+    let format = data[1];
+    let invalid = |x: &str| ImageError::Invalid(x.to_string());
+
+    // Header fields are whitespace separated and may be followed by comments
+    let mut position = 2;
+    let mut fields = Vec::new();
+    let field_count = if format == b'1' || format == b'4' {
+        2
+    } else {
+        3
+    };
+    while fields.len() < field_count {
+        while position < data.len() && data[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if data.get(position) == Some(&b'#') {
+            while position < data.len() && data[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+        let start = position;
+        while position < data.len() && data[position].is_ascii_digit() {
+            position += 1;
+        }
+        let field = std::str::from_utf8(&data[start..position]).unwrap_or_default();
+        fields.push(
+            field
+                .parse::<usize>()
+                .map_err(|_| invalid("header is damaged"))?,
+        );
+    }
+    let (width, height) = (fields[0], fields[1]);
+    let max = fields.get(2).copied().unwrap_or(1).clamp(1, 65535);
+    let scale = |x: usize| (x.min(max) * 255 / max) as u8;
+    // Every pixel takes at least a bit of data
+    let count = width
+        .checked_mul(height)
+        .filter(|x| *x <= data.len() * 8)
+        .ok_or_else(|| invalid("pixel data is too short"))?;
+
+    // Binary formats have exactly one whitespace byte after the header, and use
+    // 2 bytes per sample above 255
+    let raw = &data[(position + 1).min(data.len())..];
+    let plain = || {
+        String::from_utf8_lossy(&data[position..])
+            .split_whitespace()
+            .filter_map(|x| x.parse::<usize>().ok())
+            .map(scale)
+            .collect::<Vec<u8>>()
+    };
+    let samples = || match max > 255 {
+        true => raw
+            .chunks_exact(2)
+            .map(|x| scale((x[0] as usize) << 8 | x[1] as usize))
+            .collect::<Vec<u8>>(),
+        false => raw.iter().map(|x| scale(*x as usize)).collect(),
+    };
+    let rgb = |values: Vec<u8>| -> Vec<u8> {
+        values
+            .chunks_exact(3)
+            .map(|x| luma(x[0], x[1], x[2]))
+            .collect()
+    };
+    let pixels: Vec<u8> = match format {
+        // Plain PBM, 1 is black (off)
+        b'1' => data[position..]
+            .iter()
+            .filter(|x| **x == b'0' || **x == b'1')
+            .map(|x| if *x == b'1' { 0 } else { 255 })
+            .collect(),
+        b'2' => plain(),
+        b'3' => rgb(plain()),
+        // Raw PBM, rows are padded to whole bytes
+        b'4' => raw
+            .chunks(width.div_ceil(8).max(1))
+            .take(height)
+            .flat_map(|row| {
+                (0..width).map(
+                    move |i| match row.get(i / 8).map(|x| x >> (7 - i % 8) & 1) {
+                        Some(1) => 0,
+                        _ => 255,
+                    },
+                )
+            })
+            .collect(),
+        b'5' => samples(),
+        _ => rgb(samples()),
+    };
+    if pixels.len() < count {
+        return Err(invalid("pixel data is too short"));
+    }
+    Ok(Image {
+        width,
+        height,
+        pixels: pixels[..count].to_vec(),
+    })
+}
+
+/// Reads a PNG image, treating transparency as off
+#[cfg(feature = "png")]
+fn decode_png(data: &[u8]) -> Result<Image, ImageError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let pixels = buffer[..info.buffer_size()]
+        .chunks_exact(info.color_type.samples())
+        .map(|x| match *x {
+            [gray] => gray,
+            [gray, alpha] => (gray as u32 * alpha as u32 / 255) as u8,
+            [red, green, blue] => luma(red, green, blue),
+            [red, green, blue, alpha] => (luma(red, green, blue) as u32 * alpha as u32 / 255) as u8,
+            _ => 0,
+        })
+        .collect();
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+/// PNG images can not be read without the 'png' feature
+#[cfg(not(feature = "png"))]
+fn decode_png(_data: &[u8]) -> Result<Image, ImageError> {
+    Err(ImageError::UnknownFormat)
+}
@@ -2,47 +2,214 @@
 //! sig_rp2040_applet - Smaller applet squares for the sig_rp2040_board main
 //! binary
 //!
+//! The client module has typed clients for sending these commands to a
+//! running board (BoardClient requires the 'client' feature). The image module
+//! converts PBM/PGM/PPM and PNG (requires the 'png' feature) images to grids.
+//!
 //! Commands:
 //!
 //! CreateApplet - Creates a new applet assigned to the requesting TCP stream
-//!     Parameters: 1 u8 from 0-3
+//!     Parameters: 1 u8 from 0-8
 //!         0 - Applet separator is empty (all LED's off)
 //!         1 - Applet separator is solid (all LED's on)
 //!         2 - Applet separator is dotted (alternating LED's on & off)
 //!         3 - Applet seprator is variable (default off)
+//!         4 - Applet separator is dashed
+//!         5 - Applet separator is a single center dot
+//!         6 - Applet separator is a gradient (dark to bright)
+//!         7 - Applet separator is a progress bar (default empty)
+//!         8 - Applet separator is animated marching ants
+//!     Optional "separator" field ("Empty", "Solid", "Dotted", "Variable",
+//!     "Dashed", "CenterDot", "Gradient", "Progress" or "MarchingAnts")
+//!     replaces the 1st parameter (the orientation is then the only one)
+//!     Optional 2nd parameter: 1 u8 from 0-3 (applet orientation)
+//!         0 - Normal
+//!         1 - Rotated 180 degrees
+//!         2 - Mirrored horizontally (left and right swapped)
+//!         3 - Mirrored vertically (top and bottom swapped)
+//!     Optional "session": true field returns a session token (8 bytes,
+//!     big-endian) after the response code
+//!     Optional "allocate" field lets the board choose the slot (the chosen
+//!     app_num follows the response code as 1 u8):
+//!         "Fixed" - Exactly app_num (default)
+//!         "FirstFree" - The first free grid slot
+//!         "Preferred" - app_num, or the first free grid slot if it is taken
+//!     Optional "slot" field names the slot instead of app_num ("bar", "top",
+//!     "middle" or "bottom" as the matrix is mounted, unless the board names
+//!     them differently)
+//!     Optional "priority" field lets the applet cover or preempt lower
+//!     priority applets (if the board allows it)
+//!     Optional "carousel": true field shares a slot taken by another carousel
+//!     applet, each shown in turn for the board's dwell time (or "dwell"
+//!     seconds)
+//!     Optional "transition" field ("None", "Fade", "WipeUp", "WipeDown",
+//!     "Slide" or "Dissolve") and "transition_frames" field choose the effect
+//!     shown when the applet appears, is replaced or is removed
+//!     Optional "on_disconnect" field chooses what happens to the applet when
+//!     its stream disconnects (default "Clear"):
+//!         "Clear" - Applet is removed
+//!         {"Keep": N} - Last frame is kept for N seconds
+//!         "KeepForever" - Last frame is kept until another applet replaces it
+//!         {"Fade": N} - Last frame fades out over N seconds
+//!
+//! Resume - Reattaches a reconnecting stream to the applet it created
+//!     Parameters: 8 u8 session token (big-endian) returned by CreateApplet
+//!
+//! Hello - Checks protocol compatibility and describes the board (app_num is
+//! ignored)
+//!     Parameters: 1 u8 protocol version of the client
+//!     Response: always one JSON encoded HelloResponse line (a "response" field
+//!               chooses the encoding of following responses)
 //!
 //! UpdateGrid - Rewrites the current 9x10 applet grid with new values
 //!     Parameters: 90 u8 representing grid brightnesses - rows then columns
 //!                 (1st 10 is row1, 2nd 10 is row2, etc.)
 //!
 //! UpdateBar - Rewrites the current 9x1 applet separator
-//!     Parameters: 9 u8 representing separator brightnesses
-//!     Note: Returns error if separator is not variable
+//!     Parameters: 9 u8 representing separator brightnesses (1 u8 from 0-100
+//!                 for a progress bar separator)
+//!     Note: Returns error if separator is not variable or a progress bar
 //!
+//! SetSeparatorStyle - Changes the separator style of the applet
+//!     Parameters: 1 u8 from 0-8 (as in CreateApplet) or none with a
+//!                 "separator" field
+//!
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
-use serde::Deserialize;
+pub mod client;
+pub mod image;
 
+/// Version of the command protocol described above
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Oldest client protocol version the board accepts
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+/// Most parameters a command may have (an UpdateGrid's 90)
+pub const MAX_PARAMETERS: usize = 90;
+/// Time between animation ticks (marching ants move one LED per tick)
+pub const TICK_INTERVAL: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Applet {
     grid: [[u8; 9]; 10],
     separator_type: Separator,
     separator: [u8; 9],
+    #[serde(default)]
+    orientation: Orientation,
+    /// Token a reconnecting stream can resume the applet with
+    #[serde(default)]
+    session: Option<u64>,
+    /// What happens to the applet when its stream disconnects
+    #[serde(default)]
+    on_disconnect: OrphanPolicy,
+    /// Higher priority applets may cover or preempt lower priority ones
+    #[serde(default)]
+    priority: u8,
+    /// Applet may share its slot with other carousel applets
+    #[serde(default)]
+    carousel: bool,
+    /// Seconds the applet is shown per carousel turn (0 for the board default)
+    #[serde(default)]
+    dwell: u16,
+    /// Effect shown when the applet appears, is replaced or is removed (None
+    /// for the board default)
+    #[serde(default)]
+    transition: Option<Transition>,
+    /// Frames the transition takes (0 for the board default)
+    #[serde(default)]
+    transition_frames: u16,
 }
 
 impl Applet {
     pub fn new(separator_type: Separator) -> Self {
         Applet {
-            separator: match separator_type {
-                Separator::Empty => [0; 9],
-                Separator::Solid => [255; 9],
-                Separator::Dotted => [255, 0, 255, 0, 255, 0, 255, 0, 255],
-                Separator::Variable => [0; 9],
-            },
-            separator_type: separator_type,
+            separator: separator_type.pattern(),
+            separator_type,
             grid: [[0; 9]; 10],
+            orientation: Orientation::Normal,
+            session: None,
+            on_disconnect: OrphanPolicy::Clear,
+            priority: 0,
+            carousel: false,
+            dwell: 0,
+            transition: None,
+            transition_frames: 0,
         }
     }
 
-    pub fn command_applet(&mut self, command: &Command) -> Result<(), &'static str> {
+    /// Changes the separator style (resetting the separator to its pattern)
+    pub fn set_separator_type(&mut self, separator_type: Separator) {
+        self.separator_type = separator_type;
+        self.separator = separator_type.pattern();
+    }
+
+    /// Sets the orientation the applet's grid is displayed in
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// What happens to the applet when its stream disconnects
+    pub fn on_disconnect(&self) -> OrphanPolicy {
+        self.on_disconnect
+    }
+
+    /// Sets what happens to the applet when its stream disconnects
+    pub fn set_on_disconnect(&mut self, on_disconnect: OrphanPolicy) {
+        self.on_disconnect = on_disconnect;
+    }
+
+    /// Priority used to cover or preempt other applets
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Sets the priority used to cover or preempt other applets
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+
+    /// Whether the applet may share its slot with other carousel applets
+    pub fn is_carousel(&self) -> bool {
+        self.carousel
+    }
+
+    /// Sets whether the applet may share its slot with other carousel applets
+    /// and how many seconds it is shown per turn (0 for the board default)
+    pub fn set_carousel(&mut self, carousel: bool, dwell: u16) {
+        self.carousel = carousel;
+        self.dwell = dwell;
+    }
+
+    /// Seconds the applet is shown per carousel turn (0 for the board default)
+    pub fn dwell(&self) -> u16 {
+        self.dwell
+    }
+
+    /// Transition effect and frames (None and 0 for the board defaults)
+    pub fn transition(&self) -> (Option<Transition>, u16) {
+        (self.transition, self.transition_frames)
+    }
+
+    /// Sets the transition effect and frames (None and 0 for the board
+    /// defaults)
+    pub fn set_transition(&mut self, transition: Option<Transition>, frames: u16) {
+        self.transition = transition;
+        self.transition_frames = frames;
+    }
+
+    /// Session token the applet can be resumed with
+    pub fn session(&self) -> Option<u64> {
+        self.session
+    }
+
+    /// Sets the session token the applet can be resumed with
+    pub fn set_session(&mut self, session: Option<u64>) {
+        self.session = session;
+    }
+
+    pub fn command_applet(&mut self, command: &Command) -> Result<(), String> {
         match command.opcode {
             Opcode::UpdateGrid => {
                 // UpdateGrid command is 90 characters long
@@ -54,7 +221,7 @@ impl Applet {
                             }
                         }
                     }
-                    _ => return Err("Invalid parameter length"),
+                    x => return Err(format!("Invalid parameter length: expected 90 got {x}")),
                 }
                 Ok(())
             }
@@ -67,43 +234,610 @@ impl Applet {
                                 self.separator[i] = command.parameters[i];
                             }
                         }
-                        _ => return Err("Invalid parameter length"),
+                        x => return Err(format!("Invalid parameter length: expected 9 got {x}")),
+                    },
+                    // Progress bars are filled from a single 0-100 value
+                    Separator::Progress => match command.parameters.as_slice() {
+                        [x] if *x <= 100 => self.separator = Separator::progress(*x),
+                        [x] => return Err(format!("Invalid progress: expected 0-100 got {x}")),
+                        x => {
+                            return Err(format!(
+                                "Invalid parameter length: expected 1 got {}",
+                                x.len()
+                            ))
+                        }
                     },
-                    _ => return Err("Bar not variable"),
+                    _ => return Err("Bar not variable".to_string()),
                 }
                 Ok(())
             }
-            Opcode::CreateApplet => return Err("Applet cannot sign new applet"),
+            Opcode::SetSeparatorStyle => {
+                let separator_type = command
+                    .separator_style()
+                    .ok_or("Invalid separator style".to_string())?;
+                self.set_separator_type(separator_type);
+                Ok(())
+            }
+            Opcode::CreateApplet => Err("Applet cannot sign new applet".to_string()),
+            Opcode::Resume => Err("Applet cannot resume applet".to_string()),
+            Opcode::Hello => Err("Applet cannot greet client".to_string()),
         }
     }
 
-    pub fn get_board(&self) -> [[u8; 9]; 11] {
+    /// Region (separator and grid) the applet shows at an animation tick
+    /// (marching ants move one LED every tick)
+    pub fn get_board(&self, tick: u64) -> [[u8; 9]; 11] {
         let mut output: [[u8; 9]; 11] = [[0; 9]; 11];
-        output[0] = self.separator.clone();
-        for i in 1..11 {
-            output[i] = self.grid[i - 1].clone();
-        }
+        // Marching ants move along the separator over time
+        let separator = match self.separator_type {
+            Separator::MarchingAnts => Separator::marching_ants(tick),
+            _ => self.separator,
+        };
+        // Separator always stays on top, so only horizontal changes affect it
+        output[0] = match self.orientation {
+            Orientation::Rotate180 | Orientation::MirrorHorizontal => {
+                Orientation::MirrorHorizontal.apply(&[separator])[0]
+            }
+            _ => separator,
+        };
+        output[1..11].copy_from_slice(&self.orientation.apply(&self.grid));
         output
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Separator {
     Empty,
     Solid,
     Dotted,
     Variable,
+    Dashed,
+    CenterDot,
+    Gradient,
+    /// Filled from the left by a 0-100 value (UpdateBar with 1 parameter)
+    Progress,
+    /// Dashes moving along the separator
+    MarchingAnts,
+}
+
+impl Separator {
+    /// Converts a CreateApplet separator parameter (0-8)
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Separator::Empty),
+            1 => Some(Separator::Solid),
+            2 => Some(Separator::Dotted),
+            3 => Some(Separator::Variable),
+            4 => Some(Separator::Dashed),
+            5 => Some(Separator::CenterDot),
+            6 => Some(Separator::Gradient),
+            7 => Some(Separator::Progress),
+            8 => Some(Separator::MarchingAnts),
+            _ => None,
+        }
+    }
+
+    /// Converts to a CreateApplet separator parameter (0-8)
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Separator::Empty => 0,
+            Separator::Solid => 1,
+            Separator::Dotted => 2,
+            Separator::Variable => 3,
+            Separator::Dashed => 4,
+            Separator::CenterDot => 5,
+            Separator::Gradient => 6,
+            Separator::Progress => 7,
+            Separator::MarchingAnts => 8,
+        }
+    }
+
+    /// Initial separator brightnesses of the style
+    pub fn pattern(&self) -> [u8; 9] {
+        match self {
+            Separator::Empty | Separator::Variable => [0; 9],
+            Separator::Solid => [255; 9],
+            Separator::Dotted => [255, 0, 255, 0, 255, 0, 255, 0, 255],
+            Separator::Dashed => [255, 255, 0, 255, 255, 255, 0, 255, 255],
+            Separator::CenterDot => [0, 0, 0, 0, 255, 0, 0, 0, 0],
+            Separator::Gradient => [0, 32, 64, 96, 128, 159, 191, 223, 255],
+            Separator::Progress => Separator::progress(0),
+            Separator::MarchingAnts => Separator::marching_ants(0),
+        }
+    }
+
+    /// Progress bar separator filled to a 0-100 value (the last lit LED is
+    /// dimmed to show part of a step)
+    pub fn progress(value: u8) -> [u8; 9] {
+        let level = value.min(100) as f32 * 9.0 / 100.0;
+        let mut output = [0; 9];
+        for (i, pixel) in output.iter_mut().enumerate() {
+            *pixel = ((level - i as f32).clamp(0.0, 1.0) * 255.0) as u8;
+        }
+        output
+    }
+
+    /// Marching ants separator at an animation tick (moves one LED per tick)
+    pub fn marching_ants(tick: u64) -> [u8; 9] {
+        let mut output = [0; 9];
+        for (i, pixel) in output.iter_mut().enumerate() {
+            if !(i as u64 + 3 - tick % 3).is_multiple_of(3) {
+                *pixel = 255;
+            }
+        }
+        output
+    }
+}
+
+/// What happens to an applet when the stream that created it disconnects
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum OrphanPolicy {
+    /// Applet is removed immediately
+    #[default]
+    Clear,
+    /// Last frame is kept for a number of seconds
+    Keep(u32),
+    /// Last frame is kept until another applet replaces it
+    KeepForever,
+    /// Last frame fades out over a number of seconds
+    Fade(u32),
+}
+
+/// Orientation of a matrix (or part of a matrix) relative to how it is drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    Rotate180,
+    MirrorHorizontal,
+    MirrorVertical,
+}
+
+impl Orientation {
+    /// Converts a CreateApplet orientation parameter (0-3)
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Orientation::Normal),
+            1 => Some(Orientation::Rotate180),
+            2 => Some(Orientation::MirrorHorizontal),
+            3 => Some(Orientation::MirrorVertical),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of a row-major matrix transformed by this orientation
+    pub fn apply<const W: usize, const H: usize>(&self, input: &[[u8; W]; H]) -> [[u8; W]; H] {
+        let mut output = [[0; W]; H];
+        for (i, row) in output.iter_mut().enumerate() {
+            for (j, pixel) in row.iter_mut().enumerate() {
+                let (x, y) = self.source(i, j, W, H);
+                *pixel = input[x][y];
+            }
+        }
+        output
+    }
+
+    /// Row and column of a width x height matrix that this orientation moves
+    /// to row i, column j
+    pub fn source(&self, i: usize, j: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Orientation::Normal => (i, j),
+            Orientation::Rotate180 => (height - 1 - i, width - 1 - j),
+            Orientation::MirrorHorizontal => (i, width - 1 - j),
+            Orientation::MirrorVertical => (height - 1 - i, j),
+        }
+    }
+}
+
+/// Effect shown while a region changes from one applet to another
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Transition {
+    /// The region changes at once
+    #[default]
+    None,
+    /// The previous applet fades into the next
+    Fade,
+    /// The next applet is uncovered from the bottom up
+    WipeUp,
+    /// The next applet is uncovered from the top down
+    WipeDown,
+    /// The next applet pushes the previous one up
+    Slide,
+    /// Pixels change to the next applet one by one in a scattered order
+    Dissolve,
+}
+
+impl Transition {
+    /// Mixes rows of the previous and next region 'progress' (0.0-1.0) of the
+    /// way through the transition
+    pub fn apply(&self, from: &[[u8; 9]], to: &[[u8; 9]], progress: f32) -> Vec<[u8; 9]> {
+        let height = to.len();
+        let shift = ((height as f32 * progress).round() as usize).min(height);
+        match self {
+            Transition::None => to.to_vec(),
+            Transition::Fade => from
+                .iter()
+                .zip(to)
+                .map(|(x, y)| {
+                    let mut row = [0; 9];
+                    for j in 0..9 {
+                        row[j] = (x[j] as f32 * (1.0 - progress) + y[j] as f32 * progress) as u8;
+                    }
+                    row
+                })
+                .collect(),
+            Transition::WipeUp => [&from[..height - shift], &to[height - shift..]].concat(),
+            Transition::WipeDown => [&to[..shift], &from[shift..]].concat(),
+            Transition::Slide => [&from[shift..], &to[..shift]].concat(),
+            Transition::Dissolve => {
+                // Multiplying by a prime visits every pixel once in a scattered order
+                let pixels = height * 9;
+                let mut output = from.to_vec();
+                for i in 0..pixels {
+                    if ((i * 37) % pixels) as f32 + 0.5 < progress * pixels as f32 {
+                        output[i / 9][i % 9] = to[i / 9][i % 9];
+                    }
+                }
+                output
+            }
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Command {
     pub opcode: Opcode,
+    #[serde(default)]
     pub app_num: u8,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub screen: u8,
     pub parameters: Vec<u8>,
+    /// Typed CreateApplet or SetSeparatorStyle separator (replaces the first
+    /// parameter when set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub separator: Option<Separator>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub session: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub on_disconnect: OrphanPolicy,
+    /// CreateApplet priority for covering or preempting other applets
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub priority: u8,
+    /// CreateApplet may share a taken slot with other carousel applets
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub carousel: bool,
+    /// Seconds a carousel applet is shown per turn (0 for the board default)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dwell: u16,
+    /// Effect shown when the CreateApplet's applet appears, is replaced or is
+    /// removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    /// Frames the transition takes (0 for the board default)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub transition_frames: u16,
+    /// How CreateApplet chooses its slot
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allocate: Allocation,
+    /// Named CreateApplet slot ("bar", "top", "middle" or "bottom" by
+    /// default) used instead of the slot in app_num
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<String>,
+    /// Client chosen id echoed in JSON responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// Only respond if the client waits for a chosen slot or session token
+    /// (see Command::awaits_response)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub no_ack: bool,
+    /// Response format for this and following commands on the stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<ResponseFormat>,
 }
 
-#[derive(Deserialize, PartialEq, Eq)]
+impl Command {
+    /// Creates a command for screen 0 with every optional field at its default
+    pub fn new(opcode: Opcode, app_num: u8, parameters: Vec<u8>) -> Self {
+        Command {
+            opcode,
+            app_num,
+            screen: 0,
+            parameters,
+            separator: None,
+            session: false,
+            on_disconnect: OrphanPolicy::Clear,
+            priority: 0,
+            carousel: false,
+            dwell: 0,
+            transition: None,
+            transition_frames: 0,
+            allocate: Allocation::Fixed,
+            slot: None,
+            id: None,
+            no_ack: false,
+            response: None,
+        }
+    }
+
+    /// Creates a CreateApplet command with a typed separator
+    pub fn create(app_num: u8, separator: Separator) -> Self {
+        Command {
+            separator: Some(separator),
+            ..Command::new(Opcode::CreateApplet, app_num, Vec::new())
+        }
+    }
+
+    /// Separator and optional orientation parameter of a CreateApplet command
+    /// (None if the separator is missing or invalid)
+    pub fn creation(&self) -> Option<(Separator, Option<u8>)> {
+        match (self.separator, self.parameters.as_slice()) {
+            (Some(x), []) => Some((x, None)),
+            (Some(x), [orientation]) => Some((x, Some(*orientation))),
+            (None, [x]) => Some((Separator::from_u8(*x)?, None)),
+            (None, [x, orientation]) => Some((Separator::from_u8(*x)?, Some(*orientation))),
+            _ => None,
+        }
+    }
+
+    /// Separator style of a SetSeparatorStyle command (None if missing or
+    /// invalid)
+    pub fn separator_style(&self) -> Option<Separator> {
+        match (self.separator, self.parameters.as_slice()) {
+            (Some(x), []) => Some(x),
+            (None, [x]) => Separator::from_u8(*x),
+            _ => None,
+        }
+    }
+
+    /// Whether the board chooses the slot of a CreateApplet command (and
+    /// responds with it)
+    pub fn allocates(&self) -> bool {
+        self.opcode == Opcode::CreateApplet
+            && (self.allocate != Allocation::Fixed || self.slot.is_some())
+    }
+
+    /// Whether the board responds to the command, whether it succeeds or fails
+    /// (no-ack commands are silent unless they return a chosen slot or session
+    /// token, so a failure never leaves a response the client does not read)
+    pub fn awaits_response(&self) -> bool {
+        !self.no_ack
+            || self.opcode == Opcode::Hello
+            || self.allocates()
+            || (self.opcode == Opcode::CreateApplet && self.session)
+    }
+
+    /// Creates a Hello command for this protocol version
+    pub fn hello() -> Self {
+        Command::new(Opcode::Hello, 0, vec![PROTOCOL_VERSION])
+    }
+
+    /// Creates a Hello command asking for an encoding of following responses
+    pub fn hello_with(encoding: ResponseFormat) -> Self {
+        Command {
+            response: Some(encoding),
+            ..Command::hello()
+        }
+    }
+
+    /// Whether the board supports the protocol version of a Hello command
+    pub fn supported_version(&self) -> bool {
+        self.parameters
+            .first()
+            .is_some_and(|x| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(x))
+    }
+}
+
+/// How CreateApplet chooses the slot of a new applet
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Allocation {
+    /// Exactly the requested slot (error 34 if it is taken)
+    #[default]
+    Fixed,
+    /// The first free grid slot (the requested slot is ignored)
+    FirstFree,
+    /// The requested slot, or the first free grid slot if it is taken
+    Preferred,
+}
+
+/// Leaves optional fields out of serialized commands when not used
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Opcode {
     CreateApplet,
     UpdateGrid,
     UpdateBar,
+    Resume,
+    Hello,
+    SetSeparatorStyle,
+}
+
+impl Opcode {
+    /// Every opcode the board supports
+    pub const ALL: [Opcode; 6] = [
+        Opcode::CreateApplet,
+        Opcode::UpdateGrid,
+        Opcode::UpdateBar,
+        Opcode::Resume,
+        Opcode::Hello,
+        Opcode::SetSeparatorStyle,
+    ];
+}
+
+/// Response to a Hello command describing the board
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct HelloResponse {
+    /// 0 if the client's protocol version is supported, otherwise 42
+    pub code: u8,
+    pub version: u8,
+    pub min_version: u8,
+    pub opcodes: Vec<Opcode>,
+    /// Screens addressed by the "screen" field
+    pub screens: u8,
+    /// Applet slots on each screen (the largest if they differ)
+    pub slots: u8,
+    /// LED columns across every matrix
+    pub width: u16,
+    /// LED rows of each matrix
+    pub height: u16,
+    /// Seconds an applet with a session can be resumed after disconnecting
+    pub session_grace: u64,
+    /// Matrices making up each screen, left to right (4 slots and 9 LED
+    /// columns each)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrices: Vec<u8>,
+    /// Screens with a disconnected matrix (their applets are shown once it
+    /// reconnects)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disconnected: Vec<u8>,
+    /// Encoding of following responses on the stream
+    #[serde(default)]
+    pub encoding: ResponseFormat,
+    /// Most parameters a command may have (0 if not reported)
+    #[serde(default)]
+    pub max_parameters: u16,
+    /// Longest command in bytes the board accepts (0 if not reported)
+    #[serde(default)]
+    pub buffer_size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+}
+
+/// How the board responds to commands on a stream
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum ResponseFormat {
+    /// Single u8 response code
+    #[default]
+    Byte,
+    /// One JSON encoded Response per line
+    Json,
+}
+
+/// JSON response to a command (ResponseFormat::Json)
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub code: u8,
+    pub name: ResponseCode,
+    pub message: String,
+    /// Session token of a CreateApplet command with a session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+    /// Slot the board chose for a CreateApplet command that allocates one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_num: Option<u8>,
+    /// A matrix of the command's screen is disconnected (the applet is shown
+    /// once it reconnects)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub disconnected: bool,
+    /// Id of the command being responded to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+    /// Sent without a command (e.g. the stream's applet was preempted)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub notice: bool,
+}
+
+impl Response {
+    /// Creates a response with a detailed message (or the code's description)
+    pub fn new(code: u8, message: Option<String>) -> Self {
+        let name = ResponseCode::from_u8(code);
+        Response {
+            code,
+            name,
+            message: message.unwrap_or_else(|| name.description().to_string()),
+            session: None,
+            app_num: None,
+            disconnected: false,
+            request_id: None,
+            notice: false,
+        }
+    }
+}
+
+/// Single u8 code the board responds to every command with
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ResponseCode {
+    Success = 0,
+    Covered = 2,
+    ReadFailed = 10,
+    InvalidUtf8 = 20,
+    InvalidJson = 21,
+    InvalidApplet = 30,
+    NotOwner = 31,
+    StatusBarGrid = 32,
+    CommandFailed = 33,
+    AppletExists = 34,
+    InvalidScreen = 35,
+    InvalidSession = 36,
+    Preempted = 37,
+    Restored = 38,
+    InvalidSeparator = 40,
+    InvalidOrientation = 41,
+    UnsupportedVersion = 42,
+    Unknown = 255,
+}
+
+impl ResponseCode {
+    /// Converts a response byte (unrecognised codes are Unknown)
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ResponseCode::Success,
+            2 => ResponseCode::Covered,
+            10 => ResponseCode::ReadFailed,
+            20 => ResponseCode::InvalidUtf8,
+            21 => ResponseCode::InvalidJson,
+            30 => ResponseCode::InvalidApplet,
+            31 => ResponseCode::NotOwner,
+            32 => ResponseCode::StatusBarGrid,
+            33 => ResponseCode::CommandFailed,
+            34 => ResponseCode::AppletExists,
+            35 => ResponseCode::InvalidScreen,
+            36 => ResponseCode::InvalidSession,
+            37 => ResponseCode::Preempted,
+            38 => ResponseCode::Restored,
+            40 => ResponseCode::InvalidSeparator,
+            41 => ResponseCode::InvalidOrientation,
+            42 => ResponseCode::UnsupportedVersion,
+            _ => ResponseCode::Unknown,
+        }
+    }
+
+    /// Whether the command was processed (even if the applet is covered)
+    pub fn is_success(&self) -> bool {
+        matches!(self, ResponseCode::Success | ResponseCode::Covered)
+    }
+
+    /// Meaning of the response code
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResponseCode::Success => "Command successfully processed",
+            ResponseCode::Covered => {
+                "Command successfully processed but the applet is covered by a higher priority applet"
+            }
+            ResponseCode::ReadFailed => "Failed to read data from stream",
+            ResponseCode::InvalidUtf8 => "Failed to parse stream data as UTF-8",
+            ResponseCode::InvalidJson => "Failed to parse stream data as JSON",
+            ResponseCode::InvalidApplet => "Command uses invalid applet number",
+            ResponseCode::NotOwner => "Command attempts to modify applet stream did not create",
+            ResponseCode::StatusBarGrid => "Attempt to update applet 0 grid",
+            ResponseCode::CommandFailed => "Error in commanding applet",
+            ResponseCode::AppletExists => "Attempt to create new applet when applet already exists",
+            ResponseCode::InvalidScreen => "Command uses invalid screen number",
+            ResponseCode::InvalidSession => "Invalid or expired session token",
+            ResponseCode::Preempted => "Applet was preempted by a higher priority applet",
+            ResponseCode::Restored => "Preempted applet is shown again",
+            ResponseCode::InvalidSeparator => "Invalid separator value (or number of parameters) when creating applet",
+            ResponseCode::InvalidOrientation => "Invalid orientation value when creating applet",
+            ResponseCode::UnsupportedVersion => "Unsupported protocol version",
+            ResponseCode::Unknown => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", *self as u8, self.description())
+    }
 }
@@ -0,0 +1,260 @@
+// Written by sigroot
+//! Tests the clients against a loopback board that replays scripted responses
+
+use sig_rp2040_board_applet::client::{BlockingBoardClient, ClientError};
+use sig_rp2040_board_applet::{
+    Allocation, Command, Opcode, ResponseCode, ResponseFormat, Separator,
+};
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Session token returned by the loopback board
+const TOKEN: u64 = 0x0102_0304_0506_0708;
+
+/// Accepts one client, writing one scripted response after each command it
+/// reads (empty for no-ack commands), and returns the commands it received
+fn board(responses: Vec<Vec<u8>>) -> (SocketAddr, JoinHandle<Vec<Command>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let reader = stream.try_clone().unwrap();
+        let mut commands = serde_json::Deserializer::from_reader(reader).into_iter::<Command>();
+        let mut received = Vec::new();
+        for response in responses {
+            received.push(commands.next().unwrap().unwrap());
+            stream.write_all(&response).unwrap();
+        }
+        received
+    });
+    (addr, handle)
+}
+
+/// Byte response of a successful CreateApplet with a chosen slot and session
+fn allocated(slot: u8) -> Vec<u8> {
+    let mut response = vec![0, slot];
+    response.extend_from_slice(&TOKEN.to_be_bytes());
+    response
+}
+
+/// JSON response line
+fn line(json: &str) -> Vec<u8> {
+    format!("{json}\n").into_bytes()
+}
+
+/// CreateApplet that lets the board choose its slot and starts a session
+fn allocating_create() -> Command {
+    let mut command = Command::create(1, Separator::Empty);
+    command.allocate = Allocation::FirstFree;
+    command.session = true;
+    command
+}
+
+#[test]
+fn blocking_byte_responses() {
+    let (addr, board) = board(vec![vec![0], allocated(3), vec![2], vec![34]]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    assert_eq!(
+        client.create_applet(1, Separator::Empty).unwrap(),
+        ResponseCode::Success
+    );
+    assert_eq!(
+        client.send(&allocating_create()).unwrap(),
+        ResponseCode::Success
+    );
+    assert_eq!(client.session(), Some(TOKEN));
+    // Updates go to the chosen slot and covered applets are not errors
+    assert_eq!(
+        client.update_grid(&[[1; 9]; 10]).unwrap(),
+        ResponseCode::Covered
+    );
+    assert!(matches!(
+        client.create_applet(2, Separator::Empty),
+        Err(ClientError::Response(ResponseCode::AppletExists))
+    ));
+
+    let received = board.join().unwrap();
+    assert_eq!(received[2].opcode, Opcode::UpdateGrid);
+    assert_eq!(received[2].app_num, 3);
+    assert_eq!(received[2].parameters, vec![1; 90]);
+}
+
+#[test]
+fn blocking_json_responses() {
+    let (addr, board) = board(vec![
+        line(&format!(
+            r#"{{"code":0,"name":"Success","message":"","app_num":2,"session":{TOKEN}}}"#
+        )),
+        line(r#"{"code":40,"name":"InvalidSeparator","message":"Invalid separator"}"#),
+        line(
+            r#"{"code":0,"version":1,"min_version":1,"opcodes":["Hello"],"screens":1,"slots":4,"width":9,"height":34,"session_grace":30,"encoding":"Json"}"#,
+        ),
+    ]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    let mut command = allocating_create();
+    command.response = Some(ResponseFormat::Json);
+    assert_eq!(client.send(&command).unwrap(), ResponseCode::Success);
+    assert_eq!(client.session(), Some(TOKEN));
+    assert!(matches!(
+        client.set_separator_style(Separator::Variable),
+        Err(ClientError::Response(ResponseCode::InvalidSeparator))
+    ));
+    let hello = client.hello(ResponseFormat::Json).unwrap();
+    assert_eq!(hello.slots, 4);
+
+    let received = board.join().unwrap();
+    assert_eq!(received[1].app_num, 2);
+    assert_eq!(received[1].separator, Some(Separator::Variable));
+    // The format only has to be requested once per stream
+    assert_eq!(received[1].response, None);
+}
+
+#[test]
+fn blocking_hello() {
+    let (addr, board) = board(vec![
+        line(
+            r#"{"code":0,"version":1,"min_version":1,"opcodes":["Hello"],"screens":1,"slots":4,"width":9,"height":34,"session_grace":30,"encoding":"Json","max_parameters":90,"buffer_size":8192}"#,
+        ),
+        line(r#"{"code":0,"name":"Success","message":""}"#),
+    ]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    let hello = client.hello(ResponseFormat::Json).unwrap();
+    assert_eq!(hello.encoding, ResponseFormat::Json);
+    assert_eq!((hello.max_parameters, hello.buffer_size), (90, 8192));
+    // Following responses are read in the negotiated encoding
+    client.create_applet(1, Separator::Empty).unwrap();
+
+    let received = board.join().unwrap();
+    assert_eq!(received[0], Command::hello_with(ResponseFormat::Json));
+    assert_eq!(received[1].response, None);
+}
+
+#[test]
+fn blocking_no_ack() {
+    let (addr, board) = board(vec![vec![0], Vec::new(), vec![0]]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    client.create_applet(1, Separator::Empty).unwrap();
+    let mut command = Command::new(Opcode::UpdateBar, 1, vec![0; 9]);
+    command.no_ack = true;
+    assert_eq!(client.send(&command).unwrap(), ResponseCode::Success);
+    client.update_grid(&[[0; 9]; 10]).unwrap();
+    assert_eq!(board.join().unwrap().len(), 3);
+}
+
+#[test]
+fn blocking_progress() {
+    let (addr, board) = board(vec![vec![0], vec![0]]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    client.create_applet(2, Separator::Progress).unwrap();
+    client.update_progress(60).unwrap();
+
+    let received = board.join().unwrap();
+    assert_eq!(received[1].opcode, Opcode::UpdateBar);
+    assert_eq!(
+        (received[1].app_num, received[1].parameters.clone()),
+        (2, vec![60])
+    );
+}
+
+#[test]
+fn blocking_skips_notices() {
+    let mut command = Command::create(1, Separator::Empty);
+    command.response = Some(ResponseFormat::Json);
+    let mut responses = line(r#"{"code":37,"name":"Preempted","message":"","notice":true}"#);
+    responses.extend(line(
+        r#"{"code":40,"name":"InvalidSeparator","message":""}"#,
+    ));
+    let (addr, board) = board(vec![responses]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    // A notice sent before the response is not taken for it
+    assert!(matches!(
+        client.send(&command),
+        Err(ClientError::Response(ResponseCode::InvalidSeparator))
+    ));
+    board.join().unwrap();
+}
+
+#[test]
+fn blocking_closed_mid_line() {
+    let mut command = Command::create(1, Separator::Empty);
+    command.response = Some(ResponseFormat::Json);
+    let (addr, board) = board(vec![br#"{"code":0"#.to_vec()]);
+    let mut client = BlockingBoardClient::connect(addr).unwrap();
+    // A response cut off by the board closing the stream is an error
+    assert!(matches!(client.send(&command), Err(ClientError::Io(_))));
+    board.join().unwrap();
+}
+
+#[cfg(feature = "client")]
+mod asynchronous {
+    use super::*;
+    use sig_rp2040_board_applet::client::BoardClient;
+
+    /// Runs a future on a single threaded runtime
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn byte_responses() {
+        let (addr, board) = board(vec![vec![0], allocated(2), vec![0], vec![36]]);
+        run(async {
+            let mut client = BoardClient::connect(addr).await.unwrap();
+            client.set_screen(1);
+            client.create_applet(0, Separator::Empty).await.unwrap();
+            assert_eq!(
+                client.send(&allocating_create()).await.unwrap(),
+                ResponseCode::Success
+            );
+            assert_eq!(client.session(), Some(TOKEN));
+            client.update_bar(&[5; 9]).await.unwrap();
+            assert!(matches!(
+                client.resume(1, 7).await,
+                Err(ClientError::Response(ResponseCode::InvalidSession))
+            ));
+        });
+
+        let received = board.join().unwrap();
+        // Commands sent directly keep their own screen
+        assert_eq!(received[1].screen, 0);
+        assert!([0, 2, 3].iter().all(|x| received[*x].screen == 1));
+        assert_eq!(received[2].app_num, 2);
+        assert_eq!(received[3].parameters, 7u64.to_be_bytes());
+    }
+
+    #[test]
+    fn json_responses() {
+        let (addr, board) = board(vec![
+            line(r#"{"code":0,"name":"Success","message":"","app_num":3}"#),
+            line(r#"{"code":2,"name":"Covered","message":""}"#),
+            line(
+                r#"{"code":42,"version":1,"min_version":1,"opcodes":[],"screens":1,"slots":4,"width":9,"height":34,"session_grace":30}"#,
+            ),
+        ]);
+        run(async {
+            let mut client = BoardClient::connect(addr).await.unwrap();
+            let mut command = Command::create(0, Separator::Empty);
+            command.allocate = Allocation::Preferred;
+            command.response = Some(ResponseFormat::Json);
+            client.send(&command).await.unwrap();
+            assert_eq!(client.session(), None);
+            assert_eq!(
+                client.update_grid(&[[0; 9]; 10]).await.unwrap(),
+                ResponseCode::Covered
+            );
+            assert!(matches!(
+                client.hello(ResponseFormat::Byte).await,
+                Err(ClientError::Response(ResponseCode::UnsupportedVersion))
+            ));
+        });
+
+        let received = board.join().unwrap();
+        assert_eq!(received[1].app_num, 3);
+    }
+}
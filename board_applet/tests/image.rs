@@ -0,0 +1,128 @@
+// Written by sigroot
+//! Tests converting images to applet grids
+
+use sig_rp2040_board_applet::image::{dither, Dither, Fit, Image, ImageError, Tone};
+
+#[test]
+fn decode_netpbm() {
+    // Plain PBM with a comment, 1 is black
+    let image = Image::decode(b"P1\n# icon\n3 2\n1 0 1\n0 1 0\n").unwrap();
+    assert_eq!((image.width, image.height), (3, 2));
+    assert_eq!(image.pixels, vec![0, 255, 0, 255, 0, 255]);
+
+    // Raw PBM rows are padded to whole bytes
+    let image = Image::decode(b"P4\n9 2\n\xff\x80\x00\x00").unwrap();
+    assert_eq!(image.pixels[..9], [0; 9]);
+    assert_eq!(
+        image.pixels[9..],
+        [255, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+
+    // PGM values are scaled to the maximum value
+    let image = Image::decode(b"P2 2 1 15 0 15").unwrap();
+    assert_eq!(image.pixels, vec![0, 255]);
+    let image = Image::decode(b"P5 2 1 65535\n\x00\x00\xff\xff").unwrap();
+    assert_eq!(image.pixels, vec![0, 255]);
+
+    // PPM colors are converted to brightness
+    let image = Image::decode(b"P6 2 1 255\n\xff\x00\x00\xff\xff\xff").unwrap();
+    assert_eq!(image.pixels, vec![76, 255]);
+
+    assert!(matches!(
+        Image::decode(b"P5 9 10 255\n\x00"),
+        Err(ImageError::Invalid(_))
+    ));
+    assert!(matches!(
+        Image::decode(b"GIF89a"),
+        Err(ImageError::UnknownFormat)
+    ));
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn decode_png() {
+    // Red, white, black and half transparent white
+    let image = Image::decode(include_bytes!("fixtures/pixels.png")).unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.pixels, vec![76, 255, 0, 128]);
+}
+
+#[test]
+fn resize() {
+    let image = Image {
+        width: 4,
+        height: 2,
+        pixels: vec![0, 100, 200, 255, 0, 100, 200, 255],
+    };
+    // Neighbouring pixels are averaged
+    let half = image.resize(2, 1, Fit::Stretch);
+    assert_eq!(half.pixels, vec![50, 227]);
+    // Cropping keeps the center
+    let square = image.resize(1, 1, Fit::Crop);
+    assert_eq!(square.pixels, vec![150]);
+    // Containing leaves the unused rows off
+    let contained = image.resize(2, 3, Fit::Contain);
+    assert_eq!(contained.pixels, vec![0, 0, 50, 227, 0, 0]);
+    // Nothing is left of an image resized to nothing
+    for fit in [Fit::Stretch, Fit::Crop, Fit::Contain] {
+        let empty = image.resize(0, 3, fit);
+        assert_eq!((empty.width, empty.height), (0, 3));
+        assert!(empty.pixels.is_empty());
+        assert!(image.resize(2, 0, fit).pixels.is_empty());
+    }
+}
+
+#[test]
+fn grids() {
+    let image = Image {
+        width: 18,
+        height: 20,
+        pixels: (0..360)
+            .map(|x| if x % 18 < 9 { 40 } else { 200 })
+            .collect(),
+    };
+    let grid = image.to_grid(Fit::Stretch, Tone::Gray);
+    assert_eq!(grid[0], [40, 40, 40, 40, 120, 200, 200, 200, 200]);
+    let grid = image.to_grid(Fit::Stretch, Tone::Threshold(128));
+    assert_eq!(grid[9], [0, 0, 0, 0, 0, 255, 255, 255, 255]);
+    let matrix = image.to_matrix(Fit::Stretch, Tone::Gray);
+    assert_eq!(matrix[0], matrix[33]);
+}
+
+#[test]
+fn dithering() {
+    // Without a dither every pixel rounds to the nearest level
+    let mut pixels = vec![40, 50, 100, 200];
+    dither(&mut pixels, 4, 4, Dither::None, 0);
+    assert_eq!(pixels, vec![0, 85, 85, 170]);
+
+    // Half brightness lights half of a Bayer matrix
+    let mut pixels = vec![128; 16];
+    dither(&mut pixels, 4, 2, Dither::Bayer, 0);
+    assert_eq!(pixels.iter().filter(|x| **x == 255).count(), 8);
+    assert_eq!(pixels[..4], [255, 0, 255, 0]);
+
+    // Error diffusion keeps the average brightness
+    let mut pixels = vec![64; 90];
+    dither(&mut pixels, 9, 2, Dither::FloydSteinberg, 0);
+    let lit = pixels.iter().filter(|x| **x == 255).count();
+    assert!((20..=25).contains(&lit), "{lit} of 90 lit");
+
+    // Over 16 frames a pixel is lit for its share of the time
+    let lit = (0..16)
+        .filter(|frame| {
+            let mut pixel = [64];
+            dither(&mut pixel, 1, 2, Dither::Temporal, *frame);
+            pixel[0] == 255
+        })
+        .count();
+    assert_eq!(lit, 4);
+
+    let image = Image {
+        width: 9,
+        height: 10,
+        pixels: (0..90).map(|x| (x * 255 / 89) as u8).collect(),
+    };
+    let grid = image.to_grid(Fit::Stretch, Tone::Dither(Dither::Bayer, 3));
+    assert!(grid.concat().iter().all(|x| [0, 128, 255].contains(x)));
+}
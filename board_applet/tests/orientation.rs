@@ -0,0 +1,28 @@
+// Written by sigroot
+//! Tests transforming matrices by their orientation
+
+use sig_rp2040_board_applet::Orientation;
+
+const INPUT: [[u8; 3]; 2] = [[1, 2, 3], [4, 5, 6]];
+
+#[test]
+fn apply() {
+    assert_eq!(Orientation::Normal.apply(&INPUT), INPUT);
+    assert_eq!(Orientation::Rotate180.apply(&INPUT), [[6, 5, 4], [3, 2, 1]]);
+    assert_eq!(
+        Orientation::MirrorHorizontal.apply(&INPUT),
+        [[3, 2, 1], [6, 5, 4]]
+    );
+    assert_eq!(
+        Orientation::MirrorVertical.apply(&INPUT),
+        [[4, 5, 6], [1, 2, 3]]
+    );
+}
+
+#[test]
+fn apply_twice_restores() {
+    for orientation in (0..4).filter_map(Orientation::from_u8) {
+        assert_eq!(orientation.apply(&orientation.apply(&INPUT)), INPUT);
+    }
+    assert_eq!(Orientation::from_u8(4), None);
+}
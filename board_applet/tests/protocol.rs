@@ -0,0 +1,247 @@
+// Written by sigroot
+//! Golden tests pinning the JSON wire format of commands documented in the
+//! README
+
+use sig_rp2040_board_applet::{
+    Allocation, Command, HelloResponse, Opcode, OrphanPolicy, Response, ResponseCode,
+    ResponseFormat, Separator, Transition,
+};
+
+/// Parses a command and checks it serializes back to the same JSON
+fn round_trip(json: &str) -> Command {
+    let command: Command = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_string(&command).unwrap(), json);
+    command
+}
+
+#[test]
+fn create_applet_with_numeric_separator() {
+    let command = round_trip(r#"{"opcode":"CreateApplet","app_num":1,"parameters":[2]}"#);
+    assert_eq!(command, Command::new(Opcode::CreateApplet, 1, vec![2]));
+    assert_eq!(command.creation(), Some((Separator::Dotted, None)));
+}
+
+#[test]
+fn create_applet_with_typed_separator() {
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":2,"parameters":[1],"separator":"Variable"}"#,
+    );
+    assert_eq!(command.creation(), Some((Separator::Variable, Some(1))));
+    assert_eq!(
+        serde_json::to_string(&Command::create(1, Separator::Solid)).unwrap(),
+        r#"{"opcode":"CreateApplet","app_num":1,"parameters":[],"separator":"Solid"}"#
+    );
+}
+
+#[test]
+fn create_applet_with_invalid_separator() {
+    let command = round_trip(r#"{"opcode":"CreateApplet","app_num":1,"parameters":[9]}"#);
+    assert_eq!(command.creation(), None);
+    let command = round_trip(r#"{"opcode":"CreateApplet","app_num":1,"parameters":[]}"#);
+    assert_eq!(command.creation(), None);
+}
+
+#[test]
+fn create_applet_with_optional_fields() {
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":3,"screen":1,"parameters":[3,2],"session":true,"on_disconnect":{"Keep":60}}"#,
+    );
+    assert_eq!(command.screen, 1);
+    assert!(command.session);
+    assert_eq!(command.on_disconnect, OrphanPolicy::Keep(60));
+    assert_eq!(command.creation(), Some((Separator::Variable, Some(2))));
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":1,"parameters":[0],"on_disconnect":"KeepForever"}"#,
+    );
+    assert_eq!(command.on_disconnect, OrphanPolicy::KeepForever);
+}
+
+#[test]
+fn update_grid() {
+    let grid = (0..90).collect::<Vec<u8>>();
+    let json = format!(
+        r#"{{"opcode":"UpdateGrid","app_num":2,"parameters":{}}}"#,
+        serde_json::to_string(&grid).unwrap()
+    );
+    assert_eq!(round_trip(&json), Command::new(Opcode::UpdateGrid, 2, grid));
+}
+
+#[test]
+fn update_bar() {
+    let command = round_trip(
+        r#"{"opcode":"UpdateBar","app_num":0,"parameters":[255,0,255,0,255,0,255,0,255]}"#,
+    );
+    assert_eq!(command.opcode, Opcode::UpdateBar);
+}
+
+#[test]
+fn resume() {
+    let token = 0x0102030405060708u64.to_be_bytes().to_vec();
+    let command = round_trip(r#"{"opcode":"Resume","app_num":1,"parameters":[1,2,3,4,5,6,7,8]}"#);
+    assert_eq!(command, Command::new(Opcode::Resume, 1, token));
+}
+
+#[test]
+fn separator_parameters() {
+    for value in 0..9 {
+        assert_eq!(Separator::from_u8(value).unwrap().to_u8(), value);
+    }
+    assert_eq!(Separator::from_u8(9), None);
+}
+
+#[test]
+fn unknown_opcode_is_rejected() {
+    assert!(
+        serde_json::from_str::<Command>(r#"{"opcode":"Draw","app_num":1,"parameters":[]}"#)
+            .is_err()
+    );
+}
+
+#[test]
+fn json_response() {
+    let mut command = Command::new(Opcode::UpdateGrid, 1, vec![0; 89]);
+    command.response = Some(ResponseFormat::Json);
+    let json = serde_json::to_string(&command).unwrap();
+    assert!(json.ends_with(r#""response":"Json"}"#));
+    assert_eq!(round_trip(&json), command);
+
+    let response = Response::new(
+        33,
+        Some("Invalid parameter length: expected 90 got 89".to_string()),
+    );
+    assert_eq!(
+        serde_json::to_string(&response).unwrap(),
+        r#"{"code":33,"name":"CommandFailed","message":"Invalid parameter length: expected 90 got 89"}"#
+    );
+    let mut response = Response::new(0, None);
+    response.session = Some(7);
+    assert_eq!(
+        serde_json::to_string(&response).unwrap(),
+        r#"{"code":0,"name":"Success","message":"Command successfully processed","session":7}"#
+    );
+
+    // Disconnected matrices do not change the success code
+    let response: Response = serde_json::from_str(
+        r#"{"code":0,"name":"Success","message":"Command successfully processed","disconnected":true}"#,
+    )
+    .unwrap();
+    assert!(response.disconnected);
+    assert!(ResponseCode::from_u8(response.code).is_success());
+    assert_eq!(ResponseCode::from_u8(1), ResponseCode::Unknown);
+}
+
+#[test]
+fn request_id_and_no_ack() {
+    let command = round_trip(
+        r#"{"opcode":"UpdateGrid","app_num":1,"parameters":[],"id":42,"no_ack":true,"response":"Json"}"#,
+    );
+    assert_eq!(command.id, Some(42));
+    assert!(command.no_ack);
+    assert!(!command.awaits_response());
+    // Clients wait for chosen slots, session tokens and Hello
+    let mut create = Command::create(0, Separator::Empty);
+    create.no_ack = true;
+    assert!(!create.awaits_response());
+    create.session = true;
+    assert!(create.awaits_response());
+    create.session = false;
+    create.slot = Some("top".to_string());
+    assert!(create.awaits_response());
+    let mut hello = Command::hello();
+    hello.no_ack = true;
+    assert!(hello.awaits_response());
+
+    let mut response = Response::new(33, None);
+    response.request_id = command.id;
+    assert_eq!(
+        serde_json::to_string(&response).unwrap(),
+        r#"{"code":33,"name":"CommandFailed","message":"Error in commanding applet","request_id":42}"#
+    );
+}
+
+#[test]
+fn hello() {
+    let command = round_trip(r#"{"opcode":"Hello","app_num":0,"parameters":[1]}"#);
+    assert_eq!(command, Command::hello());
+    assert!(command.supported_version());
+    let command: Command =
+        serde_json::from_str(r#"{"opcode":"Hello","parameters":[200]}"#).unwrap();
+    assert!(!command.supported_version());
+
+    let hello: HelloResponse = serde_json::from_str(
+        r#"{"code":0,"version":1,"min_version":1,"opcodes":["CreateApplet","UpdateGrid","UpdateBar","Resume","Hello","SetSeparatorStyle"],"screens":2,"slots":4,"width":18,"height":34,"session_grace":30}"#,
+    )
+    .unwrap();
+    assert_eq!(hello.opcodes, Opcode::ALL);
+    assert_eq!(hello.request_id, None);
+    // Boards that do not report the encoding or limits
+    assert_eq!(hello.encoding, ResponseFormat::Byte);
+    assert_eq!((hello.max_parameters, hello.buffer_size), (0, 0));
+
+    let command =
+        round_trip(r#"{"opcode":"Hello","app_num":0,"parameters":[1],"response":"Json"}"#);
+    assert_eq!(command, Command::hello_with(ResponseFormat::Json));
+}
+
+#[test]
+fn allocating_create_applet() {
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":0,"parameters":[0],"allocate":"FirstFree"}"#,
+    );
+    assert_eq!(command.allocate, Allocation::FirstFree);
+    assert!(command.allocates());
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":2,"parameters":[0],"allocate":"Preferred","slot":"top"}"#,
+    );
+    assert_eq!(command.slot.as_deref(), Some("top"));
+    assert!(!Command::new(Opcode::CreateApplet, 1, vec![0]).allocates());
+
+    let mut response = Response::new(0, None);
+    response.app_num = Some(3);
+    assert_eq!(
+        serde_json::to_string(&response).unwrap(),
+        r#"{"code":0,"name":"Success","message":"Command successfully processed","app_num":3}"#
+    );
+}
+
+#[test]
+fn create_applet_with_priority() {
+    let command =
+        round_trip(r#"{"opcode":"CreateApplet","app_num":1,"parameters":[0],"priority":5}"#);
+    assert_eq!(command.priority, 5);
+    assert!(ResponseCode::from_u8(2).is_success());
+    assert!(!ResponseCode::from_u8(37).is_success());
+}
+
+#[test]
+fn create_applet_in_carousel() {
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":0,"parameters":[0],"carousel":true,"dwell":10,"allocate":"FirstFree"}"#,
+    );
+    assert!(command.carousel);
+    assert_eq!(command.dwell, 10);
+    assert!(command.allocates());
+}
+
+#[test]
+fn create_applet_with_transition() {
+    let command = round_trip(
+        r#"{"opcode":"CreateApplet","app_num":1,"parameters":[0],"transition":"WipeUp","transition_frames":20}"#,
+    );
+    assert_eq!(command.transition, Some(Transition::WipeUp));
+    assert_eq!(command.transition_frames, 20);
+}
+
+#[test]
+fn set_separator_style() {
+    let command = round_trip(
+        r#"{"opcode":"SetSeparatorStyle","app_num":1,"parameters":[],"separator":"MarchingAnts"}"#,
+    );
+    assert_eq!(command.separator_style(), Some(Separator::MarchingAnts));
+    let command = round_trip(r#"{"opcode":"SetSeparatorStyle","app_num":1,"parameters":[7]}"#);
+    assert_eq!(command.separator_style(), Some(Separator::Progress));
+    assert_eq!(
+        Separator::progress(50),
+        [255, 255, 255, 255, 127, 0, 0, 0, 0]
+    );
+}
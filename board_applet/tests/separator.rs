@@ -0,0 +1,79 @@
+// Written by sigroot
+//! Tests changing an applet's separator style and animating its separator
+
+use sig_rp2040_board_applet::{Applet, Command, Opcode, Orientation, Separator};
+
+/// SetSeparatorStyle command with its parameters
+fn set_style(parameters: Vec<u8>) -> Command {
+    Command::new(Opcode::SetSeparatorStyle, 1, parameters)
+}
+
+#[test]
+fn set_separator_type() {
+    let mut applet = Applet::new(Separator::Variable);
+    applet
+        .command_applet(&Command::new(Opcode::UpdateBar, 1, vec![9; 9]))
+        .unwrap();
+    assert_eq!(applet.get_board(0)[0], [9; 9]);
+
+    // Changing the style replaces the separator with the style's pattern
+    applet.set_separator_type(Separator::Dotted);
+    assert_eq!(applet.get_board(0)[0], Separator::Dotted.pattern());
+    applet.set_separator_type(Separator::Variable);
+    assert_eq!(applet.get_board(0)[0], [0; 9]);
+
+    // Only variable and progress separators can be updated afterwards
+    applet.set_separator_type(Separator::Solid);
+    let bar = Command::new(Opcode::UpdateBar, 1, vec![9; 9]);
+    assert!(applet.command_applet(&bar).is_err());
+    applet.set_separator_type(Separator::Progress);
+    let bar = Command::new(Opcode::UpdateBar, 1, vec![50]);
+    applet.command_applet(&bar).unwrap();
+    assert_eq!(applet.get_board(0)[0], Separator::progress(50));
+}
+
+#[test]
+fn set_separator_style_command() {
+    let mut applet = Applet::new(Separator::Empty);
+    applet.command_applet(&set_style(vec![4])).unwrap();
+    assert_eq!(applet.get_board(0)[0], Separator::Dashed.pattern());
+
+    // The typed separator field replaces the parameter
+    let mut command = set_style(vec![]);
+    command.separator = Some(Separator::Gradient);
+    applet.command_applet(&command).unwrap();
+    assert_eq!(applet.get_board(0)[0], Separator::Gradient.pattern());
+
+    // Invalid styles leave the separator as it was
+    for parameters in [vec![9], vec![], vec![1, 2]] {
+        assert!(applet.command_applet(&set_style(parameters)).is_err());
+    }
+    let mut command = set_style(vec![1]);
+    command.separator = Some(Separator::Solid);
+    assert!(applet.command_applet(&command).is_err());
+    assert_eq!(applet.get_board(0)[0], Separator::Gradient.pattern());
+}
+
+#[test]
+fn marching_ants() {
+    // Two of every three LEDs are lit, moving one LED per tick
+    let ants: Vec<[u8; 9]> = (0..4).map(Separator::marching_ants).collect();
+    assert_eq!(ants[0], [0, 255, 255, 0, 255, 255, 0, 255, 255]);
+    assert_eq!(ants[1], [255, 0, 255, 255, 0, 255, 255, 0, 255]);
+    assert_eq!(ants[2], [255, 255, 0, 255, 255, 0, 255, 255, 0]);
+    assert_eq!(ants[3], ants[0]);
+    assert_eq!(Separator::MarchingAnts.pattern(), ants[0]);
+
+    // Applets draw the ants of the tick they are shown at
+    let mut applet = Applet::new(Separator::Empty);
+    applet.command_applet(&set_style(vec![8])).unwrap();
+    assert_eq!(applet.get_board(0)[0], ants[0]);
+    assert_eq!(applet.get_board(1)[0], ants[1]);
+    assert_eq!(applet.get_board(u64::MAX)[0], ants[0]);
+
+    // Mirrored applets march the other way
+    applet.set_orientation(Orientation::MirrorHorizontal);
+    let mut mirrored = ants[1];
+    mirrored.reverse();
+    assert_eq!(applet.get_board(1)[0], mirrored);
+}
@@ -0,0 +1,91 @@
+// Written by sigroot
+//! Tests mixing regions part of the way through each transition
+
+use sig_rp2040_board_applet::Transition;
+
+/// Rows of a grid holding their row number (0-9) and 100 more than it
+fn regions() -> ([[u8; 9]; 10], [[u8; 9]; 10]) {
+    let mut from = [[0; 9]; 10];
+    let mut to = [[0; 9]; 10];
+    for i in 0..10 {
+        from[i] = [i as u8; 9];
+        to[i] = [100 + i as u8; 9];
+    }
+    (from, to)
+}
+
+/// First value of each row
+fn column(rows: &[[u8; 9]]) -> Vec<u8> {
+    rows.iter().map(|x| x[0]).collect()
+}
+
+#[test]
+fn none() {
+    let (from, to) = regions();
+    for progress in [0.0, 0.5, 1.0] {
+        assert_eq!(Transition::None.apply(&from, &to, progress), to);
+    }
+}
+
+#[test]
+fn fade() {
+    let (from, to) = regions();
+    assert_eq!(Transition::Fade.apply(&from, &to, 0.0), from);
+    let half = Transition::Fade.apply(&from, &to, 0.5);
+    assert_eq!(column(&half), [50, 51, 52, 53, 54, 55, 56, 57, 58, 59]);
+    assert!(half.iter().all(|x| x.iter().all(|y| *y == x[0])));
+    assert_eq!(Transition::Fade.apply(&from, &to, 1.0), to);
+}
+
+#[test]
+fn wipe_up() {
+    let (from, to) = regions();
+    assert_eq!(Transition::WipeUp.apply(&from, &to, 0.0), from);
+    let half = Transition::WipeUp.apply(&from, &to, 0.5);
+    assert_eq!(column(&half), [0, 1, 2, 3, 4, 105, 106, 107, 108, 109]);
+    assert_eq!(Transition::WipeUp.apply(&from, &to, 1.0), to);
+}
+
+#[test]
+fn wipe_down() {
+    let (from, to) = regions();
+    assert_eq!(Transition::WipeDown.apply(&from, &to, 0.0), from);
+    let half = Transition::WipeDown.apply(&from, &to, 0.5);
+    assert_eq!(column(&half), [100, 101, 102, 103, 104, 5, 6, 7, 8, 9]);
+    assert_eq!(Transition::WipeDown.apply(&from, &to, 1.0), to);
+}
+
+#[test]
+fn slide() {
+    let (from, to) = regions();
+    assert_eq!(Transition::Slide.apply(&from, &to, 0.0), from);
+    // The previous region moves up out of the grid as the next one follows
+    let half = Transition::Slide.apply(&from, &to, 0.5);
+    assert_eq!(column(&half), [5, 6, 7, 8, 9, 100, 101, 102, 103, 104]);
+    assert_eq!(Transition::Slide.apply(&from, &to, 1.0), to);
+}
+
+#[test]
+fn dissolve() {
+    let (from, to) = regions();
+    assert_eq!(Transition::Dissolve.apply(&from, &to, 0.0), from);
+    // Half of the pixels have changed, spread over the whole grid
+    let half = Transition::Dissolve.apply(&from, &to, 0.5);
+    let changed: Vec<usize> = half
+        .iter()
+        .map(|x| x.iter().filter(|y| **y >= 100).count())
+        .collect();
+    assert_eq!(changed.iter().sum::<usize>(), 45);
+    assert!(changed.iter().all(|x| *x > 0 && *x < 9));
+    assert_eq!(Transition::Dissolve.apply(&from, &to, 1.0), to);
+}
+
+#[test]
+fn status_bar() {
+    // The status bar region is a single row
+    let from = [[0; 9]];
+    let to = [[200; 9]];
+    assert_eq!(Transition::WipeUp.apply(&from, &to, 0.4), from);
+    assert_eq!(Transition::WipeUp.apply(&from, &to, 0.5), to);
+    assert_eq!(Transition::Fade.apply(&from, &to, 0.5), [[100; 9]]);
+}
@@ -0,0 +1,240 @@
+// Written by sigroot
+//! sig_rp2040_board - applets as the board hosts them
+//!
+//! An Applet from the applet crate is only what is drawn and the settings its
+//! CreateApplet chose. The board keeps which stream owns it, the applets it
+//! covers, when it expires and whether it was preempted beside it
+
+use sig_rp2040_applet::{Applet, OrphanPolicy};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Id of the next applet placed on the board
+static NEXT_APPLET_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An applet in (or waiting for) a slot and the board's bookkeeping for it
+#[derive(Debug)]
+pub struct BoardApplet {
+    pub applet: Applet,
+    /// Id that tells applets apart (restored applets are given a new one)
+    id: u64,
+    /// Id of the stream that owns the applet (0 if none)
+    owner: u64,
+    /// Lower priority applet hidden beneath this one
+    covered: Option<Box<BoardApplet>>,
+    /// When the applet was orphaned or restored (None if owned)
+    orphaned_at: Option<Instant>,
+    /// Applet was restored from a snapshot and has not been resumed since
+    restored: bool,
+    /// When an orphaned applet is removed (never if None)
+    expires: Option<Instant>,
+    /// Applet is below every priority (built-in applets any other covers)
+    lowest_priority: bool,
+    /// Applet is hidden beneath the higher priority applet that preempted it
+    preempted: bool,
+    /// Response code the owning stream is sent without a command
+    notice: Option<u8>,
+}
+
+impl BoardApplet {
+    /// Hosts an applet owned by a stream under a new id
+    pub fn new(applet: Applet, owner: u64) -> Self {
+        BoardApplet {
+            applet,
+            id: NEXT_APPLET_ID.fetch_add(1, Ordering::Relaxed),
+            owner,
+            covered: None,
+            orphaned_at: None,
+            restored: false,
+            expires: None,
+            lowest_priority: false,
+            preempted: false,
+            notice: None,
+        }
+    }
+
+    /// Hosts an applet restored from a snapshot (orphaned until resumed)
+    pub fn restored(applet: Applet) -> Self {
+        let mut restored = BoardApplet::new(applet, 0);
+        restored.set_orphaned(true);
+        restored.restored = true;
+        restored
+    }
+
+    /// Id that tells applets apart
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Id of the stream that owns the applet (0 if none)
+    pub fn owner(&self) -> u64 {
+        self.owner
+    }
+
+    /// Sets the id of the stream that owns the applet
+    pub fn set_owner(&mut self, owner: u64) {
+        self.owner = owner;
+    }
+
+    /// Whether the applet has no owning stream (e.g. restored from a snapshot)
+    pub fn is_orphaned(&self) -> bool {
+        self.orphaned_at.is_some()
+    }
+
+    /// Marks the applet as owned or not owned by a stream
+    pub fn set_orphaned(&mut self, orphaned: bool) {
+        if orphaned {
+            self.orphaned_at = Some(Instant::now());
+        } else {
+            self.orphaned_at = None;
+            self.expires = None;
+            self.restored = false;
+        }
+    }
+
+    /// When the applet was orphaned or restored (None if owned)
+    pub fn orphaned_at(&self) -> Option<Instant> {
+        self.orphaned_at
+    }
+
+    /// Whether the applet was restored from a snapshot and not resumed since
+    pub fn is_restored(&self) -> bool {
+        self.restored
+    }
+
+    /// When the orphaned applet is removed (never if None)
+    pub fn expires(&self) -> Option<Instant> {
+        self.expires
+    }
+
+    /// Sets when the orphaned applet is removed (never if None)
+    pub fn set_expires(&mut self, expires: Option<Instant>) {
+        self.expires = expires;
+    }
+
+    /// Whether the applet is an orphan whose time has run out
+    pub fn expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|x| x <= now)
+    }
+
+    /// Whether the applet is below every priority (any other applet covers it)
+    pub fn is_lowest_priority(&self) -> bool {
+        self.lowest_priority
+    }
+
+    /// Sets whether the applet is below every priority (any other applet
+    /// covers it)
+    pub fn set_lowest_priority(&mut self, lowest_priority: bool) {
+        self.lowest_priority = lowest_priority;
+    }
+
+    /// Whether the applet is hidden beneath the applet that preempted it
+    pub fn is_preempted(&self) -> bool {
+        self.preempted
+    }
+
+    /// Marks the applet as preempted (notice 37) or shown again (notice 38)
+    pub fn set_preempted(&mut self, preempted: bool) {
+        if self.preempted != preempted {
+            self.notice = Some(if preempted { 37 } else { 38 });
+        }
+        self.preempted = preempted;
+    }
+
+    /// Takes the response code waiting to be sent to the owning stream
+    pub fn take_notice(&mut self) -> Option<u8> {
+        self.notice.take()
+    }
+
+    /// Hides another applet (and everything it covers) beneath this one
+    pub fn cover(&mut self, below: BoardApplet) {
+        self.covered = Some(Box::new(below));
+    }
+
+    /// Removes and returns the applet hidden beneath this one
+    pub fn take_covered(&mut self) -> Option<BoardApplet> {
+        self.covered.take().map(|x| *x)
+    }
+
+    /// Puts another applet in this one's place (above the applets this one
+    /// covers), returning this one
+    pub fn replace(&mut self, mut next: BoardApplet) -> BoardApplet {
+        next.covered = self.covered.take();
+        std::mem::replace(self, next)
+    }
+
+    /// Finds the applet (this or one it covers) with an id
+    pub fn find_id(&mut self, id: u64) -> Option<&mut BoardApplet> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.covered.as_deref_mut()?.find_id(id)
+    }
+
+    /// Finds the applet (this or one it covers) owned by a stream and whether
+    /// it is covered
+    pub fn find_owned(&mut self, owner: u64) -> Option<(&mut BoardApplet, bool)> {
+        if self.owner == owner {
+            return Some((self, false));
+        }
+        let below = self.covered.as_deref_mut()?;
+        below.find_owned(owner).map(|(x, _)| (x, true))
+    }
+
+    /// Removes the applet owned by a stream from beneath this one (the
+    /// applets it covered stay hidden)
+    pub fn remove_covered(&mut self, owner: u64) -> Option<BoardApplet> {
+        let below = self.covered.as_deref_mut()?;
+        if below.owner != owner {
+            return below.remove_covered(owner);
+        }
+        let mut removed = self.take_covered()?;
+        self.covered = removed.covered.take();
+        Some(removed)
+    }
+
+    /// Finds the orphaned applet (this or one it covers) that can be resumed
+    /// with a session token and whether it is covered
+    pub fn find_session(&mut self, token: u64) -> Option<(&mut BoardApplet, bool)> {
+        if self.is_orphaned() && self.applet.session() == Some(token) {
+            return Some((self, false));
+        }
+        let below = self.covered.as_deref_mut()?;
+        below.find_session(token).map(|(x, _)| (x, true))
+    }
+
+    /// Removes orphaned applets whose time has run out from beneath this one
+    /// (the applets they covered stay hidden)
+    pub fn remove_expired(&mut self, now: Instant) {
+        while let Some(mut removed) = self.covered.take() {
+            if !removed.expired(now) {
+                self.covered = Some(removed);
+                break;
+            }
+            self.covered = removed.covered.take();
+        }
+        if let Some(below) = self.covered.as_deref_mut() {
+            below.remove_expired(now);
+        }
+    }
+
+    /// Region (separator and grid) the applet shows at an animation tick,
+    /// fading it out over its Fade seconds once it is orphaned
+    pub fn get_board(&self, tick: u64, now: Instant) -> [[u8; 9]; 11] {
+        let mut output = self.applet.get_board(tick);
+        if let (OrphanPolicy::Fade(seconds), Some(start)) =
+            (self.applet.on_disconnect(), self.orphaned_at)
+        {
+            let elapsed = now.saturating_duration_since(start).as_secs_f32();
+            let level = match seconds {
+                0 => 0.0,
+                x => 1.0 - (elapsed / x as f32).min(1.0),
+            };
+            for pixel in output.iter_mut().flatten() {
+                *pixel = (*pixel as f32 * level) as u8;
+            }
+        }
+        output
+    }
+}
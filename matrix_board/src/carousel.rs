@@ -0,0 +1,193 @@
+// Written by sigroot
+//! sig_rp2040_board - carousels of applets taking turns in a slot
+//!
+//! Carousel applets waiting for their turn are kept here instead of in the
+//! applet table. Each slot with a carousel records which of its applets holds
+//! the carousel's place (shown, or covered by a higher priority applet) and
+//! the applets waiting after it
+
+use crate::applet::BoardApplet;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Applets taking turns in one slot
+struct Queue {
+    /// Id of the applet holding the carousel's place in the slot
+    shown: u64,
+    /// Ids of the applets waiting for their turn, in order
+    waiting: VecDeque<u64>,
+    /// When the shown applet's turn started
+    turn_started: Instant,
+}
+
+/// Every carousel of the board, by (matrix, slot)
+#[derive(Default)]
+pub struct Carousels {
+    /// Applets waiting for their turn (not in the applet table)
+    applets: Vec<BoardApplet>,
+    /// Carousel of each slot with applets waiting
+    queues: BTreeMap<(usize, usize), Queue>,
+}
+
+impl Carousels {
+    /// Number of applets waiting in a slot's carousel
+    pub fn waiting(&self, location: (usize, usize)) -> usize {
+        self.queues.get(&location).map_or(0, |x| x.waiting.len())
+    }
+
+    /// Id of the applet holding a slot's carousel place (None without applets
+    /// waiting)
+    pub fn shown(&self, location: (usize, usize)) -> Option<u64> {
+        self.queues.get(&location).map(|x| x.shown)
+    }
+
+    /// Adds an applet to the end of a slot's carousel (starting one held by
+    /// the applet with id 'shown' if no applet is waiting yet)
+    pub fn join(
+        &mut self,
+        location: (usize, usize),
+        shown: u64,
+        applet: BoardApplet,
+        now: Instant,
+    ) {
+        let queue = self.queues.entry(location).or_insert_with(|| Queue {
+            shown,
+            waiting: VecDeque::new(),
+            turn_started: now,
+        });
+        queue.waiting.push_back(applet.id());
+        self.applets.push(applet);
+    }
+
+    /// Applets waiting in each slot's carousel, in order
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &BoardApplet)> {
+        self.queues.iter().flat_map(move |(location, queue)| {
+            queue.waiting.iter().filter_map(move |id| {
+                Some((*location, self.applets.iter().find(|x| x.id() == *id)?))
+            })
+        })
+    }
+
+    /// Finds an applet waiting in a slot's carousel
+    fn find(
+        &mut self,
+        location: (usize, usize),
+        matches: impl Fn(&BoardApplet) -> bool,
+    ) -> Option<&mut BoardApplet> {
+        let waiting = &self.queues.get(&location)?.waiting;
+        self.applets
+            .iter_mut()
+            .find(|x| waiting.contains(&x.id()) && matches(x))
+    }
+
+    /// Finds the applet waiting in a slot's carousel owned by a stream
+    pub fn find_owned(&mut self, location: (usize, usize), owner: u64) -> Option<&mut BoardApplet> {
+        self.find(location, |x| x.owner() == owner)
+    }
+
+    /// Finds the orphaned applet waiting in a slot's carousel that can be
+    /// resumed with a session token
+    pub fn find_session(
+        &mut self,
+        location: (usize, usize),
+        token: u64,
+    ) -> Option<&mut BoardApplet> {
+        self.find(location, |x| {
+            x.is_orphaned() && x.applet.session() == Some(token)
+        })
+    }
+
+    /// Removes the applet waiting in a slot's carousel owned by a stream
+    pub fn remove_owned(&mut self, location: (usize, usize), owner: u64) -> Option<BoardApplet> {
+        let id = self.find_owned(location, owner)?.id();
+        self.remove_waiting(|x| x.id() == id).pop()
+    }
+
+    /// Removes waiting orphaned applets whose time has run out
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.remove_waiting(|x| x.expired(now));
+    }
+
+    /// Removes every waiting applet that matches (carousels left without
+    /// applets waiting end)
+    fn remove_waiting(&mut self, matches: impl Fn(&BoardApplet) -> bool) -> Vec<BoardApplet> {
+        let (removed, kept) = std::mem::take(&mut self.applets)
+            .into_iter()
+            .partition(|x| matches(x));
+        self.applets = kept;
+        let ids: Vec<u64> = removed.iter().map(|x: &BoardApplet| x.id()).collect();
+        self.queues.retain(|_, x| {
+            x.waiting.retain(|id| !ids.contains(id));
+            !x.waiting.is_empty()
+        });
+        removed
+    }
+
+    /// Ends a slot's carousel, removing the applets waiting in it
+    pub fn clear(&mut self, location: (usize, usize)) {
+        if let Some(queue) = self.queues.remove(&location) {
+            self.applets.retain(|x| !queue.waiting.contains(&x.id()));
+        }
+    }
+
+    /// Puts the next waiting applet in the place of the slot's applet with an
+    /// id if that applet holds the slot's carousel place, returning the
+    /// replaced applet (None if it does not or no applet is waiting)
+    pub fn pass_turn(
+        &mut self,
+        slot: &mut Option<BoardApplet>,
+        location: (usize, usize),
+        id: u64,
+        now: Instant,
+    ) -> Option<BoardApplet> {
+        let queue = self.queues.get_mut(&location).filter(|x| x.shown == id)?;
+        let place = slot.as_mut()?.find_id(id)?;
+        let next = queue.waiting.pop_front()?;
+        queue.shown = next;
+        queue.turn_started = now;
+        if queue.waiting.is_empty() {
+            self.queues.remove(&location);
+        }
+        let i = self.applets.iter().position(|x| x.id() == next)?;
+        Some(place.replace(self.applets.swap_remove(i)))
+    }
+
+    /// Gives the next applet its turn in each carousel whose shown applet has
+    /// been shown for its dwell time ('dwell' unless it sets its own)
+    ///
+    /// Carousels whose place is no longer held by an applet in their slot end
+    pub fn rotate(
+        &mut self,
+        applets: &mut [[Option<BoardApplet>; 4]],
+        dwell: Duration,
+        now: Instant,
+    ) {
+        let locations: Vec<(usize, usize)> = self.queues.keys().copied().collect();
+        for location in locations {
+            let slot = match applets.get_mut(location.0) {
+                Some(x) if location.1 < 4 => &mut x[location.1],
+                _ => {
+                    self.clear(location);
+                    continue;
+                }
+            };
+            let queue = &self.queues[&location];
+            let (shown, next) = (queue.shown, queue.waiting[0]);
+            let dwell = match slot.as_mut().and_then(|x| x.find_id(shown)) {
+                Some(x) if x.applet.dwell() > 0 => Duration::from_secs(x.applet.dwell() as u64),
+                Some(_) => dwell,
+                None => {
+                    self.clear(location);
+                    continue;
+                }
+            };
+            if now.saturating_duration_since(queue.turn_started) < dwell {
+                continue;
+            }
+            if let Some(previous) = self.pass_turn(slot, location, shown, now) {
+                self.join(location, next, previous, now);
+            }
+        }
+    }
+}
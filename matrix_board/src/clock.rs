@@ -0,0 +1,292 @@
+// Written by sigroot
+//! sig_rp2040_board - built-in clock applet
+//!
+//! The clock takes a slot the way a stream's CreateApplet would
+
+use crate::applet::BoardApplet;
+use crate::carousel::Carousels;
+use crate::{command_builtin, create_builtin, Options, NEXT_STREAM_ID};
+
+use sig_rp2040_applet::{Allocation, Command, Opcode, Separator};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::interval;
+
+/// How often the clock is redrawn (fast enough to blink twice a second)
+pub const CLOCK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 3x5 digits 0-9, one row per u8 (bit 2 is the left column)
+pub const DIGITS_3X5: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// How the clock's grid shows the time
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Face {
+    /// Hours over minutes in the 3x5 font
+    Digits,
+    /// Binary coded digits of hours, minutes and seconds as columns
+    Binary,
+    /// Hour and minute dots on a ring around the grid
+    Ring,
+}
+
+/// Built-in clock settings
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Clock {
+    pub face: Face,
+    pub twelve_hour: bool,
+    /// Seconds east of UTC (None for the system's local time)
+    pub offset: Option<i32>,
+    /// Blink the middle of the grid every second
+    pub blink: bool,
+    /// Fill the separator with the seconds of the minute
+    pub seconds: bool,
+}
+
+/// Converts a time zone offset ("utc", "utc+2", "utc-3:30") to seconds
+fn parse_offset(value: &str) -> Option<i32> {
+    let offset = value.strip_prefix("utc")?;
+    if offset.is_empty() {
+        return Some(0);
+    }
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = offset[1..].split_once(':').unwrap_or((&offset[1..], "0"));
+    // Unsigned so that a second sign ("utc+-3") is rejected
+    let hours = hours.parse::<u32>().ok().filter(|x| *x <= 14)?;
+    let minutes = minutes.parse::<u32>().ok().filter(|x| *x < 60)?;
+    Some(sign * (hours * 3600 + minutes * 60) as i32)
+}
+
+/// Converts a clock description ("<face>[:<slot>][,<option>]...") to the
+/// clock and the CreateApplet command that places it
+///
+/// Faces are digits, binary and ring. Options are 12h, 24h, blink, seconds and
+/// a time zone offset (e.g. utc+2, default local time)
+pub fn parse_clock(value: &str) -> Option<(Clock, Command)> {
+    let mut parts = value.split(',');
+    let first = parts.next()?;
+    let (face, slot) = match first.split_once(':') {
+        Some((face, slot)) => (face, Some(slot)),
+        None => (first, None),
+    };
+    let mut clock = Clock {
+        face: match face {
+            "digits" => Face::Digits,
+            "binary" => Face::Binary,
+            "ring" => Face::Ring,
+            _ => return None,
+        },
+        twelve_hour: false,
+        offset: None,
+        blink: false,
+        seconds: false,
+    };
+    for option in parts {
+        match option {
+            "12h" => clock.twelve_hour = true,
+            "24h" => clock.twelve_hour = false,
+            "blink" => clock.blink = true,
+            "seconds" => clock.seconds = true,
+            _ => clock.offset = Some(parse_offset(option)?),
+        }
+    }
+
+    let separator = match clock.seconds {
+        true => Separator::Progress,
+        false => Separator::Empty,
+    };
+    let mut command = Command::create(0, separator);
+    match slot.map(|x| (x, x.parse::<u8>())) {
+        // Without a slot the first free grid slot is used
+        None => command.allocate = Allocation::FirstFree,
+        Some((_, Ok(app_num))) => command.app_num = app_num,
+        Some((name, Err(_))) => command.slot = Some(name.to_string()),
+    }
+    Some((clock, command))
+}
+
+/// Reads the system's offset from UTC in seconds at the current time (0 if
+/// it can not be read)
+#[cfg(unix)]
+pub fn local_offset() -> i32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: tm is plain data and localtime_r only writes to the tm it is
+    // given (unlike localtime, it shares no buffer between threads)
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    match unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        true => 0,
+        false => tm.tm_gmtoff as i32,
+    }
+}
+
+/// Reads the system's offset from UTC in seconds (always 0 without libc)
+#[cfg(not(unix))]
+pub fn local_offset() -> i32 {
+    0
+}
+
+/// Copies a 3x5 digit into the grid with its top left corner at (row, column)
+fn draw_digit(grid: &mut [[u8; 9]; 10], digit: u32, row: usize, column: usize) {
+    for (i, bits) in DIGITS_3X5[digit as usize % 10].iter().enumerate() {
+        for j in 0..3 {
+            if bits & (0b100 >> j) != 0 {
+                grid[row + i][column + j] = 255;
+            }
+        }
+    }
+}
+
+/// Position on the ring around the grid a fraction (0.0-1.0) of the way
+/// clockwise from the top middle
+fn ring_position(fraction: f32) -> (usize, usize) {
+    // Top row, right column, bottom row and left column clockwise from the
+    // top left corner (34 LEDs)
+    let mut ring: Vec<(usize, usize)> = (0..9).map(|x| (0, x)).collect();
+    ring.extend((1..10).map(|x| (x, 8)));
+    ring.extend((0..8).rev().map(|x| (9, x)));
+    ring.extend((1..9).rev().map(|x| (x, 0)));
+    let step = (fraction * ring.len() as f32).round() as usize;
+    ring[(4 + step) % ring.len()]
+}
+
+/// Draws the clock's grid at a local time (time since the epoch shifted by the
+/// time zone offset)
+pub fn draw_clock(clock: &Clock, local: Duration) -> [[u8; 9]; 10] {
+    let seconds_of_day = local.as_secs() % 86400;
+    let (hours, minutes, seconds) = (
+        (seconds_of_day / 3600) as u32,
+        (seconds_of_day / 60 % 60) as u32,
+        (seconds_of_day % 60) as u32,
+    );
+    let shown_hours = match (clock.twelve_hour, hours % 12) {
+        (true, 0) => 12,
+        (true, x) => x,
+        (false, _) => hours,
+    };
+
+    let mut grid = [[0; 9]; 10];
+    match clock.face {
+        Face::Digits => {
+            // 12 hour clocks leave out the leading zero
+            if !(clock.twelve_hour && shown_hours < 10) {
+                draw_digit(&mut grid, shown_hours / 10, 0, 1);
+            }
+            draw_digit(&mut grid, shown_hours % 10, 0, 5);
+            draw_digit(&mut grid, minutes / 10, 5, 1);
+            draw_digit(&mut grid, minutes % 10, 5, 5);
+        }
+        Face::Binary => {
+            // Each bit is 2 LEDs tall, lowest bit at the bottom
+            let columns = [
+                (0, shown_hours / 10),
+                (1, shown_hours % 10),
+                (3, minutes / 10),
+                (4, minutes % 10),
+                (6, seconds / 10),
+                (7, seconds % 10),
+            ];
+            for (column, value) in columns {
+                for bit in 0..4 {
+                    if value & (1 << bit) != 0 {
+                        grid[9 - bit * 2][column] = 255;
+                        grid[8 - bit * 2][column] = 255;
+                    }
+                }
+            }
+        }
+        Face::Ring => {
+            // Dim quarter marks, then the hour and minute dots
+            for quarter in 0..4 {
+                let (row, column) = ring_position(quarter as f32 / 4.0);
+                grid[row][column] = 32;
+            }
+            let (row, column) = ring_position(((hours % 12) * 60 + minutes) as f32 / 720.0);
+            grid[row][column] = 128;
+            let (row, column) = ring_position(minutes as f32 / 60.0);
+            grid[row][column] = 255;
+        }
+    }
+
+    // Blink the middle on for the first half of every second (the binary face
+    // has no room for it)
+    if clock.blink && clock.face != Face::Binary && local.subsec_millis() < 500 {
+        grid[4][4] = 255;
+        grid[5][4] = 255;
+    }
+    grid
+}
+
+/// Places the clock applet as its CreateApplet command describes and redraws it
+/// until it is removed
+pub async fn run_clock(
+    clock: Clock,
+    command: Command,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    options: Arc<Mutex<Options>>,
+) {
+    let owner = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    let location = match create_builtin(command, owner, &applets_mutex, &carousels, &options) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{e} for clock");
+            return;
+        }
+    };
+
+    let mut offset = match clock.offset {
+        Some(x) => x,
+        None => local_offset(),
+    };
+    let mut minute = None;
+    let mut ticker = interval(CLOCK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Follow daylight saving changes of the local time
+        if clock.offset.is_none() && minute != Some(now.as_secs() / 60) {
+            minute = Some(now.as_secs() / 60);
+            offset = local_offset();
+        }
+        let local = match offset {
+            x if x >= 0 => now + Duration::from_secs(x as u64),
+            x => now.saturating_sub(Duration::from_secs(x.unsigned_abs() as u64)),
+        };
+
+        let grid = draw_clock(&clock, local);
+        let mut commands = vec![Command::new(Opcode::UpdateGrid, 0, grid.concat())];
+        // Progress bar separators are filled from a 0-100 value
+        if clock.seconds {
+            let seconds = local.as_secs() % 60;
+            commands.push(Command::new(
+                Opcode::UpdateBar,
+                0,
+                vec![(seconds * 100 / 59) as u8],
+            ));
+        }
+        if !command_builtin(&applets_mutex, &carousels, location, owner, &commands) {
+            eprintln!("Clock was removed");
+            return;
+        }
+    }
+}
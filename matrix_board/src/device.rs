@@ -0,0 +1,329 @@
+// Written by sigroot
+//! sig_rp2040_board - serial device discovery for LED matrices
+
+use crate::matrix::LedMatrixInterface;
+use crate::{init, pwm, BAUDRATE, TIMEOUT};
+
+use std::any::Any;
+use std::cell::Cell;
+use std::fs;
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::panic::{self, catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+/// USB vendor ID of Framework Computer Inc
+pub const FRAMEWORK_VID: u16 = 0x32ac;
+/// USB product ID of the Framework LED matrix input module
+pub const LED_MATRIX_PID: u16 = 0x0020;
+/// USB vendor ID of Raspberry Pi (RP2040 boards running Arduino firmware)
+pub const RASPBERRY_PI_VID: u16 = 0x2e8a;
+
+/// First wait before reopening a disconnected matrix
+pub const RECONNECT_MIN: Duration = Duration::from_millis(500);
+/// Longest wait between attempts to reopen a disconnected matrix
+pub const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// A USB serial port and the attributes used to recognise LED matrices
+pub struct DeviceInfo {
+    pub port: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial: Option<String>,
+    pub product: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Whether the USB IDs look like an LED matrix module
+    pub fn is_matrix(&self) -> bool {
+        matches!(
+            (self.vid, self.pid),
+            (Some(FRAMEWORK_VID), Some(LED_MATRIX_PID)) | (Some(RASPBERRY_PI_VID), _)
+        )
+    }
+}
+
+/// Lists every USB CDC-ACM (or USB serial) port found in sysfs (usually
+/// '/sys', changed to point tests at fixtures)
+pub fn list_devices(sys: &Path) -> Vec<DeviceInfo> {
+    let mut devices = Vec::new();
+    let entries = match fs::read_dir(sys.join("class/tty")) {
+        Ok(x) => x,
+        Err(_) => return devices,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !(name.starts_with("ttyACM") || name.starts_with("ttyUSB")) {
+            continue;
+        }
+        // 'device' links to the USB interface, the USB attributes are on its parent
+        let usb = entry.path().join("device/..");
+        let read = |attribute: &str| {
+            fs::read_to_string(usb.join(attribute))
+                .ok()
+                .map(|x| x.trim().to_string())
+        };
+        let hex = |attribute: &str| read(attribute).and_then(|x| u16::from_str_radix(&x, 16).ok());
+        devices.push(DeviceInfo {
+            port: format!("/dev/{name}"),
+            vid: hex("idVendor"),
+            pid: hex("idProduct"),
+            serial: read("serial"),
+            product: read("product"),
+        });
+    }
+    devices.sort_by(|a, b| a.port.cmp(&b.port));
+    devices
+}
+
+/// Prints every candidate serial port, marking likely LED matrices
+pub fn print_devices(sys: &Path) {
+    let devices = list_devices(sys);
+    if devices.is_empty() {
+        println!("No USB serial ports found");
+    }
+    for x in devices {
+        println!(
+            "{}\t{:04x}:{:04x}\t{}\t{}{}",
+            x.port,
+            x.vid.unwrap_or(0),
+            x.pid.unwrap_or(0),
+            x.serial.as_deref().unwrap_or("None"),
+            x.product.as_deref().unwrap_or("None"),
+            if x.is_matrix() { "\t(LED matrix)" } else { "" }
+        );
+    }
+}
+
+/// Resolves a serial port path or USB serial number to a serial port path
+pub fn resolve_device(name: &str, sys: &Path) -> Option<String> {
+    // Port paths (or Windows port names) are used as is
+    if Path::new(name).exists() || name.starts_with("COM") {
+        return Some(name.to_string());
+    }
+
+    // Search the USB serial number of each serial port
+    list_devices(sys)
+        .into_iter()
+        .find(|x| x.serial.as_deref() == Some(name))
+        .map(|x| x.port)
+}
+
+/// Finds the port of the only connected LED matrix
+///
+/// Falls back to the only CDC-ACM port if none has known LED matrix USB IDs
+pub fn find_matrix(sys: &Path) -> Result<String, String> {
+    let devices = list_devices(sys);
+    let mut candidates: Vec<&DeviceInfo> = devices.iter().filter(|x| x.is_matrix()).collect();
+    if candidates.is_empty() {
+        candidates = devices.iter().filter(|x| x.port.contains("ACM")).collect();
+    }
+    match candidates.len() {
+        0 => Err("No LED matrix found (see --list-devices)".to_string()),
+        1 => Ok(candidates[0].port.clone()),
+        _ => Err(
+            "Multiple possible LED matrices found, choose one with --device (see --list-devices)"
+                .to_string(),
+        ),
+    }
+}
+
+/// Opens an LED matrix by port path or USB serial number (or the only
+/// connected LED matrix if none is given), looking ports up in sysfs
+pub fn open_matrix(
+    name: Option<&str>,
+    sys: &Path,
+    baud_rate: u32,
+    timeout: u64,
+) -> Result<Board, String> {
+    let port = find_port(name, sys)?;
+    // The port may still disappear after being checked, and the interface
+    // panics if it can not open it
+    let interface = quietly(|| LedMatrixInterface::new_manual(&port, baud_rate, timeout))
+        .map_err(|e| format!("Could not open {port}: {}", panic_message(&e)))?;
+    Ok(Board {
+        interface,
+        name: name.map(|x| x.to_string()),
+        sys: sys.to_path_buf(),
+        port,
+        connected: true,
+        backoff: Backoff::default(),
+    })
+}
+
+thread_local! {
+    /// Whether panics on this thread are expected and reported by their caller
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs a call to the matrix interface (which panics on serial port errors)
+/// without printing its panic message, so the caller can report it once
+fn quietly<T>(f: impl FnOnce() -> T) -> std::thread::Result<T> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(|x| x.get()) {
+                default(info);
+            }
+        }));
+    });
+    QUIET.with(|x| x.set(true));
+    let result = catch_unwind(AssertUnwindSafe(f));
+    QUIET.with(|x| x.set(false));
+    result
+}
+
+/// Text of a caught panic
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(x) => x,
+        None => payload.downcast_ref::<String>().map_or("panic", |x| x),
+    }
+}
+
+/// Waits between attempts to reopen a disconnected matrix, doubling from
+/// RECONNECT_MIN up to RECONNECT_MAX after every failed attempt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub delay: Duration,
+    pub next: Instant,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            delay: RECONNECT_MIN,
+            next: Instant::now(),
+        }
+    }
+}
+
+impl Backoff {
+    /// Starts waiting the shortest time again (after a disconnect at 'now')
+    pub fn reset(&mut self, now: Instant) {
+        self.delay = RECONNECT_MIN;
+        self.next = now + self.delay;
+    }
+
+    /// Whether the next attempt may be made at 'now'
+    pub fn due(&self, now: Instant) -> bool {
+        now >= self.next
+    }
+
+    /// Doubles the wait after an attempt failed at 'now'
+    pub fn failed(&mut self, now: Instant) {
+        self.delay = (self.delay * 2).min(RECONNECT_MAX);
+        self.next = now + self.delay;
+    }
+}
+
+/// Finds and checks the serial port for a port path or USB serial number
+pub fn find_port(name: Option<&str>, sys: &Path) -> Result<String, String> {
+    let port = match name {
+        Some(x) => resolve_device(x, sys).ok_or(format!("Matrix device not found: {x}"))?,
+        None => find_matrix(sys)?,
+    };
+
+    // Report missing devices and permission problems instead of panicking
+    if !port.starts_with("COM") {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+        // A server without a controlling terminal would otherwise take the
+        // port as one and be hung up when the matrix is unplugged
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NOCTTY);
+        if let Err(e) = options.open(&port) {
+            return Err(format!("Could not open {port}: {e}"));
+        }
+    }
+    Ok(port)
+}
+
+/// An opened LED matrix that can be reopened after being disconnected
+pub struct Board {
+    pub interface: LedMatrixInterface,
+    /// Port path or USB serial number the matrix was selected by
+    pub name: Option<String>,
+    /// Sysfs the matrix is looked up in again when reconnecting
+    sys: PathBuf,
+    /// Serial port the matrix is currently opened on
+    pub port: String,
+    pub connected: bool,
+    backoff: Backoff,
+}
+
+impl Board {
+    /// Writes a frame, marking the matrix disconnected if the write fails
+    ///
+    /// Applet state is kept by the caller, so the frame is shown again once
+    /// the matrix reconnects
+    pub fn write_frame(&mut self, input_matrix: &[[u8; 9]; 34]) {
+        if !self.connected {
+            self.try_reconnect();
+            if !self.connected {
+                return;
+            }
+        }
+
+        // A panic can only leave the interface with a half written port or
+        // buffer, and the interface is not written again until try_reconnect
+        // reopens the port and initializes it (overwriting both buffers), so
+        // asserting unwind safety is sound
+        if let Err(e) = quietly(|| pwm(&mut self.interface, input_matrix)) {
+            self.disconnect(panic_message(&e));
+        }
+    }
+
+    /// Fully initializes the matrix again (after the firmware may have reset)
+    ///
+    /// The current frame is rewritten by the next 'write_frame'
+    pub fn reinit(&mut self) {
+        if !self.connected {
+            return;
+        }
+        // Unwind safe for the same reason as in 'write_frame'
+        if let Err(e) = quietly(|| init(&mut self.interface)) {
+            self.disconnect(panic_message(&e));
+        }
+    }
+
+    /// Marks the matrix as disconnected after a failed write and schedules the
+    /// first reconnection (failed reconnections are not reported again)
+    fn disconnect(&mut self, error: &str) {
+        // Only a failed write checks whether the port is still there
+        match self.port.starts_with("COM") || Path::new(&self.port).exists() {
+            true => eprintln!("LED matrix disconnected: {} ({error})", self.port),
+            false => eprintln!("LED matrix disconnected: {} (unplugged)", self.port),
+        }
+        self.connected = false;
+        self.backoff.reset(Instant::now());
+    }
+
+    /// Reopens and initializes the matrix if its retry time has passed,
+    /// doubling the wait after every failed attempt
+    pub fn try_reconnect(&mut self) {
+        if !self.backoff.due(Instant::now()) {
+            return;
+        }
+        let reopened = find_port(self.name.as_deref(), &self.sys).and_then(|port| {
+            self.interface
+                .set_port_manual(&port, BAUDRATE, TIMEOUT)
+                .map_err(|e| e.to_string())?;
+            self.port = port;
+            // Unwind safe for the same reason as in 'write_frame'
+            quietly(|| init(&mut self.interface))
+                .map_err(|_| "Failed to initialize matrix".to_string())
+        });
+        match reopened {
+            Ok(()) => {
+                println!("LED matrix reconnected: {}", self.port);
+                self.connected = true;
+            }
+            Err(_) => self.backoff.failed(Instant::now()),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// Written by sigroot
+//! sig_rp2040_board - one-shot client for showing images from scripts
+//!
+//! Converts an image to an applet grid (or the whole matrix) and sends it the
+//! way the send subcommand does
+
+use std::fs;
+use std::io;
+use std::io::Read;
+
+use crate::parse_dither;
+use crate::send::{send_commands, ClientOptions};
+use sig_rp2040_applet::image::{Fit, Image, Tone};
+use sig_rp2040_applet::{Command, Opcode, Separator};
+
+pub const IMAGE_HELP_PAGE: &str = "\
+Convert an image to a grid and send it to a running board, then exit.
+
+    $sig_rp2040_board image [-p <port>] [-a <applet>] [-s <screen>]
+                            [-k <seconds>|forever] [-f <fit>]
+                            [-t <threshold>|-d <dither>] [-z <size>]
+                            <image>
+
+Flags:
+    -p  Set port (default 27072)
+    -a  Set applet number (default 1)
+    -s  Set screen number (default 0)
+    -k  Keep the applet after disconnecting for a number of seconds or
+        'forever' (default forever)
+    -f  Fit the image by 'stretch', 'crop' or 'contain' (default stretch)
+    -t  Turn LEDs at or above a brightness 0-255 fully on and the rest off
+        (default keep every brightness)
+    -d  Reduce to a few brightness levels as <dither>[:<levels>] with the
+        dithers none, bayer, floyd-steinberg or temporal (default 4 levels,
+        can not be used with -t)
+    -z  Convert to one applet's 9x10 'grid' or the whole 9x34 'matrix' using
+        applets 0-3 and their separators (default grid)
+    -h  Display this menu
+
+<image> is a PBM, PGM, PPM or PNG file, or '-' to read it from stdin
+";
+
+/// Values collected from the image subcommand's arguments
+pub struct ImageOptions {
+    client: ClientOptions,
+    fit: Fit,
+    tone: Tone,
+    matrix: bool,
+    path: String,
+}
+
+/// Collects user parameters (every flag takes one argument and the image comes
+/// last), None if the help page should be shown
+pub fn parse_arguments(args: &[String]) -> Result<Option<ImageOptions>, String> {
+    let (path, args) = match args.split_last() {
+        Some((path, args)) if path != "-h" => (path, args),
+        _ => return Ok(None),
+    };
+    let mut options = ImageOptions {
+        client: ClientOptions::default(),
+        fit: Fit::Stretch,
+        tone: Tone::Gray,
+        matrix: false,
+        path: path.clone(),
+    };
+    // -t and -d both set how brightnesses are reduced
+    let mut tone_flag = None;
+    for pair in args.chunks(2) {
+        let (flag, value) = match pair {
+            [flag, value] if flag != "-h" => (flag.as_str(), value.as_str()),
+            _ => return Ok(None),
+        };
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid argument for {flag}: {e}");
+        if flag == "-t" || flag == "-d" {
+            if let Some(other) = tone_flag.filter(|x| *x != flag) {
+                return Err(format!("Can not use {flag} with {other}"));
+            }
+            tone_flag = Some(flag);
+        }
+        match flag {
+            _ if options.client.parse_flag(flag, value)? => (),
+            "-f" => {
+                options.fit = match value {
+                    "stretch" => Fit::Stretch,
+                    "crop" => Fit::Crop,
+                    "contain" => Fit::Contain,
+                    _ => return Err(invalid(&"expected stretch, crop or contain")),
+                }
+            }
+            "-t" => options.tone = Tone::Threshold(value.parse().map_err(|e| invalid(&e))?),
+            "-d" => {
+                let (dither, levels) = parse_dither(value)
+                    .ok_or_else(|| invalid(&"expected none, bayer, floyd-steinberg or temporal"))?;
+                options.tone = Tone::Dither(dither, levels);
+            }
+            "-z" => {
+                options.matrix = match value {
+                    "grid" => false,
+                    "matrix" => true,
+                    _ => return Err(invalid(&"expected grid or matrix")),
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(options))
+}
+
+/// Runs the image subcommand with its arguments, returning the exit code
+pub fn image(args: &[String]) -> i32 {
+    let options = match parse_arguments(args) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            println!("{IMAGE_HELP_PAGE}");
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+
+    // Read and decode the image
+    let data = match options.path.as_str() {
+        "-" => {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data).map(|_| data)
+        }
+        path => fs::read(path),
+    };
+    let image = match data
+        .map_err(|e| e.to_string())
+        .and_then(|x| Image::decode(&x).map_err(|e| e.to_string()))
+    {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not read {}: {e}", options.path);
+            return 2;
+        }
+    };
+
+    // Each applet is created (or replaces an orphan) before it is drawn
+    let create = |app_num: u8, separator: Separator| {
+        let mut command = Command::create(app_num, separator);
+        command.on_disconnect = options.client.on_disconnect;
+        command
+    };
+    let mut commands = Vec::new();
+    match options.matrix {
+        // The status bar and the applets' separators take the rows between
+        // their grids
+        true => {
+            let matrix = image.to_matrix(options.fit, options.tone);
+            for app_num in 0..4 {
+                let separator = match app_num {
+                    0 => 0,
+                    _ => 11 * (app_num - 1) + 1,
+                };
+                commands.push(create(app_num as u8, Separator::Variable));
+                commands.push(Command::new(
+                    Opcode::UpdateBar,
+                    app_num as u8,
+                    matrix[separator].to_vec(),
+                ));
+                if app_num > 0 {
+                    let grid = matrix[separator + 1..separator + 11].concat();
+                    commands.push(Command::new(Opcode::UpdateGrid, app_num as u8, grid));
+                }
+            }
+        }
+        false => {
+            let grid = image.to_grid(options.fit, options.tone);
+            let app_num = options.client.app_num;
+            commands.push(create(app_num, Separator::Empty));
+            commands.push(Command::new(Opcode::UpdateGrid, app_num, grid.concat()));
+        }
+    }
+    for command in commands.iter_mut() {
+        command.screen = options.client.screen;
+    }
+    send_commands(options.client.port, &commands)
+}
@@ -1,46 +1,258 @@
 // Written by sigroot
 //! sig_rp2040_board - library
 
-use sig_rp2040_applet::{Applet, Command, Opcode, Separator};
+use applet::BoardApplet;
+use carousel::Carousels;
+use sig_rp2040_applet::image::{dither, Dither};
+use sig_rp2040_applet::{
+    Allocation, Applet, Command, HelloResponse, Opcode, Orientation, OrphanPolicy, Response,
+    ResponseFormat, Transition, MAX_PARAMETERS, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    TICK_INTERVAL,
+};
 pub use sig_rp2040_interface as matrix;
 
-use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
 use std::net::SocketAddr;
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::task::spawn;
-use tokio::time::interval;
+use tokio::time::{interval, timeout};
 
 use tokio::io::AsyncWriteExt;
 
+pub mod applet;
+pub mod carousel;
+pub mod clock;
+pub mod device;
+pub mod image;
+pub mod resume;
+pub mod send;
+pub mod state;
+pub mod sysmon;
+pub mod system;
+pub mod transition;
+pub mod widgets;
+
 pub const ON: [[u8; 9]; 34] = [[255; 9]; 34];
 pub const OFF: [[u8; 9]; 34] = [[0; 9]; 34];
 
 pub const BAUDRATE: u32 = 1000000;
 pub const TIMEOUT: u64 = 10000;
+/// Bytes read from a stream at once (and the longest command accepted)
 pub const BUFFER_SIZE: usize = 8192;
+/// How often an idle stream is checked for notices to send
+pub const NOTICE_INTERVAL: Duration = Duration::from_millis(250);
+/// Id of the next stream to connect (applets record their owner's id)
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
 pub const HELP_PAGE: &str = "\
 Framework LED matrix controller.
 
 Acts as an interface between the Framework LED matrix and applet programs
 
-    $sig_rp2040_board [-trh] [-p <port>] [-f <framerate>]
+    $sig_rp2040_board [-trh] [-p <port>] [-f <framerate>] [-o <orientation>]
+                      [-d <device>]... [-l <layout>] [-s <state file>]
+                      [-g <seconds>] [-c <policy>] [-w <seconds>]
+                      [-e <effect>] [-x <frames>] [-q <dither>]
+                      [-n <name>=<slot>[,<name>=<slot>]...]
+                      [-b <widget>[:<screen>]]... [-m <monitor>[:<slot>]]...
+                      [-k <clock>]
+                      [--proc <path>] [--sys <path>]
+    $sig_rp2040_board --list-devices
+    $sig_rp2040_board send [-h] ...   (send a grid or bar to a running board)
+    $sig_rp2040_board image [-h] ...  (send an image to a running board)
 
 Flags:
     -t  Run a frame test
     -p  Set port (default 27072)
     -f  Set framerate (default 60)
+    -o  Set matrix orientation (normal, rotate180, mirror-h, mirror-v)
+    -d  Add a matrix by serial port path or USB serial number as
+        <device>[,<layout>] (repeatable, also --device, default is the only
+        connected matrix)
+    -l  Set the layout of matrices added without one (screens, canvas)
+        (default screens)
+    -s  Save applets to and restore applets from a state file
+    -g  Set how long applets with a session wait to be resumed (default 30)
+    -c  Set what higher priority applets do to taken slots (none, cover,
+        preempt) (default none)
+    -w  Set how long each carousel applet is shown per turn (default 5)
+    -e  Set the default transition effect between applets (none, fade,
+        wipe-up, wipe-down, slide, dissolve) (default none)
+    -x  Set how many frames transitions take (default 15)
+    -n  Name the slots a CreateApplet's \"slot\" field (and -m and -k) may
+        use, top to bottom as the matrix is mounted (default
+        bar=0,top=1,middle=2,bottom=3)
+    -q  Reduce every frame to a few brightness levels as <dither>[:<levels>]
+        with the dithers none, bayer, floyd-steinberg or temporal (default
+        4 levels, off unless given)
+    -b  Show a widget (battery, cpu, memory, seconds) on a screen's status bar
+        while no applet uses it as <widget>[:<screen>] (default screen 0),
+        taking turns with other widgets there (repeatable)
+    -m  Show a built-in system monitor applet (cpu, memory, network, disk) in
+        a slot (number or name, default the first free grid slot) (repeatable)
+    -k  Show a built-in clock as <face>[:<slot>][,<option>]... with the faces
+        digits, binary or ring and the options 12h, 24h, blink, seconds and
+        utc[+|-H[:MM]] (default local time)
     -r  Permit runtime applet replacement
     -h  Display this menu
+    --list-devices  List serial ports and mark likely LED matrices
+    --proc  Read system statistics from another /proc (e.g. test fixtures)
+    --sys   Read battery levels, disks and serial ports from another /sys
 ";
 
 pub struct Options {
     pub test: bool,
+    pub orientation: Orientation,
+    /// Matrices (indexes into the applet table) making up each screen, left
+    /// to right
+    pub screens: Vec<Vec<usize>>,
+    pub session_grace: Duration,
+    pub preemption: Preemption,
+    /// How long each carousel applet is shown per turn
+    pub dwell: Duration,
+    /// Effect used when a slot changes, unless the applets choose their own
+    pub transition: Transition,
+    /// Frames a transition takes, unless the applets choose
+    pub transition_frames: u16,
+    /// Dither and number of brightness levels every frame is reduced to (None
+    /// keeps every brightness)
+    pub dither: Option<(Dither, u8)>,
+    /// Names of slots (0-3 on each matrix) as the matrix is mounted
+    pub slot_names: Vec<(String, u8)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            test: false,
+            orientation: Orientation::Normal,
+            screens: Vec::new(),
+            session_grace: Duration::from_secs(30),
+            preemption: Preemption::None,
+            dwell: Duration::from_secs(5),
+            transition: Transition::None,
+            transition_frames: 15,
+            dither: None,
+            slot_names: parse_slot_names(DEFAULT_SLOT_NAMES).unwrap(),
+        }
+    }
+}
+
+/// What a CreateApplet with a higher priority does to the applet in its slot
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preemption {
+    /// Nothing, the slot is taken (error 34)
+    None,
+    /// Hides the applet until the higher priority applet is removed
+    Cover,
+    /// Hides the applet until the higher priority applet is removed, telling
+    /// its stream (its commands return 37 meanwhile)
+    Preempt,
+}
+
+/// Converts a preemption policy name
+pub fn parse_preemption(value: &str) -> Option<Preemption> {
+    match value {
+        "none" => Some(Preemption::None),
+        "cover" => Some(Preemption::Cover),
+        "preempt" => Some(Preemption::Preempt),
+        _ => None,
+    }
+}
+
+/// Converts a transition effect name
+pub fn parse_transition(value: &str) -> Option<Transition> {
+    match value {
+        "none" => Some(Transition::None),
+        "fade" => Some(Transition::Fade),
+        "wipe-up" => Some(Transition::WipeUp),
+        "wipe-down" => Some(Transition::WipeDown),
+        "slide" => Some(Transition::Slide),
+        "dissolve" => Some(Transition::Dissolve),
+        _ => None,
+    }
+}
+
+/// Converts a dither with an optional number of brightness levels ("bayer" or
+/// "bayer:3", default 4 levels)
+pub fn parse_dither(value: &str) -> Option<(Dither, u8)> {
+    let (name, levels) = match value.split_once(':') {
+        Some((name, levels)) => (name, levels.parse::<u8>().ok().filter(|x| *x >= 2)?),
+        None => (value, 4),
+    };
+    let dither = match name {
+        "none" => Dither::None,
+        "bayer" => Dither::Bayer,
+        "floyd-steinberg" => Dither::FloydSteinberg,
+        "temporal" => Dither::Temporal,
+        _ => return None,
+    };
+    Some((dither, levels))
+}
+
+/// How a matrix is addressed when multiple matrices are connected
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    /// The matrix is an independent screen selected by a command's "screen"
+    Screens,
+    /// The matrix is part of one wide canvas (left to right) with continuous
+    /// applet numbers (applets 0-3 on the first matrix, 4-7 on the second,
+    /// etc.) that is oriented and drawn as a whole
+    Canvas,
+}
+
+/// Converts a layout name
+pub fn parse_layout(value: &str) -> Option<Layout> {
+    match value {
+        "screens" => Some(Layout::Screens),
+        "canvas" => Some(Layout::Canvas),
+        _ => None,
+    }
+}
+
+/// Converts a device with an optional layout ("/dev/ttyACM0" or
+/// "/dev/ttyACM0,canvas", None is the -l layout)
+pub fn parse_device(value: &str) -> Option<(String, Option<Layout>)> {
+    match value.split_once(',') {
+        Some((name, layout)) => Some((name.to_string(), Some(parse_layout(layout)?))),
+        None => Some((value.to_string(), None)),
+    }
+}
+
+/// Groups matrices into screens by their layouts (canvas matrices form one
+/// screen where the first of them is)
+pub fn group_screens(layouts: &[Layout]) -> Vec<Vec<usize>> {
+    let mut screens: Vec<Vec<usize>> = Vec::new();
+    let mut canvas: Option<usize> = None;
+    for (i, layout) in layouts.iter().enumerate() {
+        match (layout, canvas) {
+            (Layout::Canvas, Some(x)) => screens[x].push(i),
+            (Layout::Canvas, None) => {
+                canvas = Some(screens.len());
+                screens.push(vec![i]);
+            }
+            (Layout::Screens, _) => screens.push(vec![i]),
+        }
+    }
+    screens
+}
+
+/// Converts an orientation name (or CreateApplet orientation number)
+pub fn parse_orientation(value: &str) -> Option<Orientation> {
+    match value {
+        "normal" => Some(Orientation::Normal),
+        "rotate180" => Some(Orientation::Rotate180),
+        "mirror-h" => Some(Orientation::MirrorHorizontal),
+        "mirror-v" => Some(Orientation::MirrorVertical),
+        _ => Orientation::from_u8(value.parse::<u8>().ok()?),
+    }
 }
 
 /// Runs a test of all pixels and varying brightnesses
@@ -83,7 +295,9 @@ pub fn init(board: &mut matrix::LedMatrixInterface) {
 /// Processes each stream (one stream per applet)
 pub async fn handle_streams(
     local_addr: SocketAddr,
-    applets_mutex: Arc<Mutex<[Option<Applet>; 4]>>,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    boards: Arc<Mutex<Vec<device::Board>>>,
     options: Arc<Mutex<Options>>,
 ) {
     // Create TCP listener from address
@@ -101,6 +315,8 @@ pub async fn handle_streams(
                     stream,
                     client_addr,
                     Arc::clone(&applets_mutex),
+                    Arc::clone(&carousels),
+                    Arc::clone(&boards),
                     Arc::clone(&options),
                 ));
             }
@@ -115,238 +331,1135 @@ pub async fn handle_streams(
 async fn run_commands(
     mut stream: TcpStream,
     client_addr: SocketAddr,
-    applets_mutex: Arc<Mutex<[Option<Applet>; 4]>>,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    boards: Arc<Mutex<Vec<device::Board>>>,
     options: Arc<Mutex<Options>>,
 ) {
-    let mut buffer: [u8; BUFFER_SIZE];
-    let mut read_data: VecDeque<char> = VecDeque::new();
-    let mut app_num = None;
+    let mut buffer = [0; BUFFER_SIZE];
+    let mut read_data: Vec<u8> = Vec::new();
+    // An overlong command was dropped (the rest of it is not reported again)
+    let mut overflowed = false;
+    let mut state = StreamState::new(client_addr);
+    let session_grace = options.try_lock().unwrap().session_grace;
 
     // Run for each recieved packet
     loop {
-        // Wait for data
-        stream
-            .readable()
-            .await
-            .expect("Error reading stream: {client_addr}");
-        // Initialize buffer
-        buffer = [0; BUFFER_SIZE];
+        // Wait for data, sending notices (JSON only) while there is none
+        match timeout(NOTICE_INTERVAL, stream.readable()).await {
+            Ok(x) => x.expect("Error reading stream: {client_addr}"),
+            Err(_) => {
+                let notices = take_notices(
+                    &mut applets_mutex.try_lock().unwrap(),
+                    &mut carousels.try_lock().unwrap(),
+                    &state,
+                    &options.try_lock().unwrap().screens,
+                );
+                if state.format == ResponseFormat::Json {
+                    for notice in notices {
+                        let data = reply_bytes(&Reply::Response(notice), state.format);
+                        stream.write_all(&data).await.unwrap();
+                    }
+                }
+                continue;
+            }
+        }
 
         // Read stream data to buffer (may not be complete packet or may be multiple packets)
-        match stream.try_read(&mut buffer) {
+        let read = match stream.try_read(&mut buffer) {
             // Stream cleanly ended, no longer connected
             Ok(0) => {
-                stop_applet(applets_mutex, app_num);
+                stop_applet(
+                    &mut applets_mutex.try_lock().unwrap(),
+                    &mut carousels.try_lock().unwrap(),
+                    &state.owned,
+                    state.id,
+                    session_grace,
+                );
                 break;
             }
             // Stream not cleanly ended, no longer connected
             Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {
-                stop_applet(applets_mutex, app_num);
+                stop_applet(
+                    &mut applets_mutex.try_lock().unwrap(),
+                    &mut carousels.try_lock().unwrap(),
+                    &state.owned,
+                    state.id,
+                    session_grace,
+                );
                 break;
             }
             // Read x bytes
-            Ok(_) => (),
+            Ok(x) => x,
             // Not ready to read
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
             // Read has failed
             Err(e) => {
                 eprintln!("Failed read: {client_addr} from {e}");
-                stream.write_u8(10).await.unwrap();
+                respond(&mut stream, state.format, None, 10, Some(e.to_string())).await;
                 panic!()
             }
         };
 
-        // Convert buffer to UTF-8 string
-        let buffer_string = match std::str::from_utf8(&buffer) {
-            Ok(x) => x.trim_matches('\0'),
-            Err(_) => {
+        // Push buffer to read_data (a character may be split between reads)
+        read_data.extend_from_slice(&buffer[..read]);
+        if let Err(e) = std::str::from_utf8(&read_data) {
+            if e.error_len().is_some() {
                 eprintln!("Could not parse stream as utf8");
-                stream.write_u8(20).await.unwrap();
+                respond(&mut stream, state.format, None, 20, None).await;
                 panic!()
             }
-        };
-
-        // Push buffer to read_data
-        for c in buffer_string.chars() {
-            match c {
-                '\0' => break,
-                _ => read_data.push_back(c),
-            }
         }
 
         // Parse every command in read_data
-        loop {
-            // Check if there is another command in read_data
-            if !read_data.contains(&'}') {
-                break;
-            };
-
-            // Pop next command
-            let mut command_string = String::with_capacity(256);
-            loop {
-                match read_data.pop_front() {
-                    Some('}') => {
-                        command_string.push('}');
-                        break;
-                    }
-                    Some(x) => command_string.push(x),
-                    None => {
-                        eprintln!(
-                            "Could not parse command:\n{:?}\nError: No '{}' in JSON",
-                            command_string.as_str().as_bytes(),
-                            '}'
-                        );
-                        stream.write_u8(21).await.unwrap();
-                        continue;
-                    }
+        for value in take_values(&mut read_data) {
+            // Parse command from JSON value to object
+            let value = match value {
+                // Numbers at the start of the rest of a dropped command
+                Ok(x) if overflowed && !x.is_object() => continue,
+                Ok(x) => x,
+                Err(_) if overflowed => {
+                    overflowed = false;
+                    continue;
                 }
-            }
-
-            // Parse command from string to object
-            let command: Command = match serde_json::de::from_str(command_string.as_str()) {
+                Err(e) => {
+                    eprintln!("Could not parse stream as JSON: {e}");
+                    respond(&mut stream, state.format, None, 21, Some(e.to_string())).await;
+                    continue;
+                }
+            };
+            overflowed = false;
+            let command: Command = match serde_json::from_value(value.clone()) {
                 Ok(x) => x,
                 Err(e) => {
-                    eprintln!(
-                        "Could not parse command:\n{:?}\nError: {e}",
-                        command_string.as_str().as_bytes()
-                    );
-                    stream.write_u8(21).await.unwrap();
+                    eprintln!("Could not parse command:\n{value}\nError: {e}");
+                    // Echo the id of commands that are valid JSON (no-ack
+                    // commands are silent)
+                    if value["no_ack"] != true {
+                        let id = value["id"].as_u64();
+                        respond(&mut stream, state.format, id, 21, Some(e.to_string())).await;
+                    }
                     continue;
                 }
             };
 
-            // Test for invalid command
-            if command.app_num > 3 {
-                eprintln!("Invalid applet number: {client_addr}");
-                stream.write_u8(30).await.unwrap();
-                panic!();
+            // Contain use of mutex locks (they can not be held while writing)
+            let (reply, close) = {
+                let connected: Vec<bool> = boards
+                    .try_lock()
+                    .unwrap()
+                    .iter()
+                    .map(|x| x.connected)
+                    .collect();
+                run_command(
+                    command,
+                    &mut state,
+                    &mut applets_mutex.try_lock().unwrap(),
+                    &mut carousels.try_lock().unwrap(),
+                    &options.try_lock().unwrap(),
+                    &connected,
+                )
+            };
+            let data = reply_bytes(&reply, state.format);
+            if !data.is_empty() {
+                stream.write_all(&data).await.unwrap();
             }
+            if close {
+                stop_applet(
+                    &mut applets_mutex.try_lock().unwrap(),
+                    &mut carousels.try_lock().unwrap(),
+                    &state.owned,
+                    state.id,
+                    session_grace,
+                );
+                return;
+            }
+        }
+
+        // Drop an unfinished command once it is longer than the buffer
+        if read_data.len() > BUFFER_SIZE {
+            eprintln!("Command longer than {BUFFER_SIZE} bytes: {client_addr}");
+            let message = format!("Command longer than {BUFFER_SIZE} bytes");
+            respond(&mut stream, state.format, None, 21, Some(message)).await;
+            read_data.clear();
+            overflowed = true;
+        }
+    }
+}
+
+/// A connected stream and the applets it created (or resumed)
+pub struct StreamState {
+    /// Owner id of the stream's applets
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub owned: Vec<(usize, usize)>,
+    pub format: ResponseFormat,
+}
+
+impl StreamState {
+    /// Creates the state of a newly connected stream with a new owner id
+    pub fn new(addr: SocketAddr) -> Self {
+        StreamState {
+            id: NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            owned: Vec::new(),
+            format: ResponseFormat::Byte,
+        }
+    }
+}
+
+/// What a stream is sent after a command
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Reply {
+    /// Nothing (no-ack commands)
+    Silent,
+    Response(Response),
+    Hello(HelloResponse),
+}
 
-            // Test if command applet number matches applet created by stream
-            match app_num {
-                Some(x) => {
-                    if x != command.app_num {
-                        eprintln!("{client_addr} attempted to modify wrong applet: applet {x}");
-                        stream.write_u8(31).await.unwrap();
-                        panic!();
+impl Reply {
+    /// Response code of the reply (0 if nothing is sent)
+    pub fn code(&self) -> u8 {
+        match self {
+            Reply::Silent => 0,
+            Reply::Response(x) => x.code,
+            Reply::Hello(x) => x.code,
+        }
+    }
+}
+
+/// Creates an error reply echoing the command's id
+fn error_reply(command: &Command, code: u8, message: Option<String>) -> Reply {
+    let mut response = Response::new(code, message);
+    response.request_id = command.id;
+    Reply::Response(response)
+}
+
+/// Runs one command of a stream against the applet table, returning the reply
+/// and whether the stream is closed
+///
+/// No-ack commands are silent (even if they fail) unless the client waits for
+/// a chosen slot or session token, so responses stay in order
+pub fn run_command(
+    command: Command,
+    state: &mut StreamState,
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    options: &Options,
+    connected: &[bool],
+) -> (Reply, bool) {
+    let awaits_response = command.awaits_response();
+    let (reply, close) = execute_command(command, state, applets, carousels, options, connected);
+    match awaits_response {
+        true => (reply, close),
+        false => (Reply::Silent, close),
+    }
+}
+
+/// Runs one command and replies to it as if the client waits for a response
+fn execute_command(
+    mut command: Command,
+    state: &mut StreamState,
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    options: &Options,
+    connected: &[bool],
+) -> (Reply, bool) {
+    let client_addr = state.addr;
+    let stream_id = state.id;
+    let owned = &mut state.owned;
+    let session_grace = options.session_grace;
+
+    // Switch the stream's response format if requested
+    if let Some(x) = command.response {
+        state.format = x;
+    }
+
+    // Find the matrix and applet the command refers to
+    let screens = &options.screens;
+
+    // Handshake describes the board instead of commanding an applet
+    if command.opcode == Opcode::Hello {
+        let hello = hello_response(&command, screens, connected, session_grace, state.format);
+        // Incompatible clients are disconnected
+        if hello.code != 0 {
+            eprintln!("Unsupported protocol version: {client_addr}");
+        }
+        let close = hello.code != 0;
+        return (Reply::Hello(hello), close);
+    }
+
+    // Longer parameter lists than any opcode takes are not stored
+    if command.parameters.len() > MAX_PARAMETERS {
+        eprintln!("Too many parameters: {client_addr}");
+        let message = format!("Too many parameters: at most {MAX_PARAMETERS}");
+        return (error_reply(&command, 33, Some(message)), false);
+    }
+
+    // Let the board choose the slot of allocating CreateApplet commands
+    let mut assigned = None;
+    if command.allocates() {
+        match allocate_slot(&command, applets, carousels, options) {
+            Ok(x) => {
+                command.app_num = x;
+                assigned = Some(x);
+            }
+            Err(30) => {
+                eprintln!("Invalid slot name: {client_addr}");
+                return (error_reply(&command, 30, None), false);
+            }
+            // The stream may try again once a slot is free
+            Err(_) => {
+                eprintln!("No free slot for applet: {client_addr}");
+                return (error_reply(&command, 34, None), false);
+            }
+        }
+    }
+    let location = match locate(&command, screens) {
+        Ok(x) => x,
+        Err(30) => {
+            eprintln!("Invalid applet number: {client_addr}");
+            return (error_reply(&command, 30, None), true);
+        }
+        Err(_) => {
+            eprintln!("Invalid screen number: {client_addr}");
+            return (error_reply(&command, 35, None), true);
+        }
+    };
+
+    // Only allow stream to modify applets it owns (or claim new ones)
+    if !owned.contains(&location)
+        && command.opcode != Opcode::CreateApplet
+        && command.opcode != Opcode::Resume
+    {
+        eprintln!(
+            "{client_addr} attempted to modify wrong applet: applet {} (screen {})",
+            location.1, location.0
+        );
+        return (error_reply(&command, 31, None), true);
+    }
+
+    let mut response = 0;
+    let mut token = None;
+    let mut message = None;
+
+    let applet = &mut applets[location.0][location.1];
+    // Orphaned applets are replaced by the next stream to create one
+    // (unless their session can still be resumed)
+    if command.opcode == Opcode::CreateApplet {
+        remove_replaceable(applet, location, carousels, session_grace);
+    }
+    match command.opcode {
+        // Reattach stream to its previous applet (even if it is covered)
+        Opcode::Resume => {
+            match resume_applet(applet, location, carousels, &command.parameters, stream_id) {
+                Some(covered) => {
+                    // The stream may already own the applet it resumes
+                    if !owned.contains(&location) {
+                        owned.push(location);
+                    }
+                    if covered {
+                        response = 2;
                     }
                 }
-                None => (),
-            }
-
-            // Response variable to avoid async with applet
-            let mut response = 0;
-
-            // Contain use of mutex lock (match requires reference during 'None')
-            {
-                let applet = &mut applets_mutex.try_lock().unwrap()[command.app_num as usize];
-                match applet {
-                    // Write command
-                    Some(x) => {
-                        if app_num == Some(0) {
-                            match command.opcode {
-                                Opcode::UpdateBar => match x.command_applet(&command) {
-                                    Ok(x) => x,
-                                    Err(_) => response = 33,
-                                },
-                                Opcode::UpdateGrid => response = 32,
-                                Opcode::CreateApplet => response = 34,
-                            }
-                        } else {
-                            if command.opcode != Opcode::CreateApplet {
-                                match x.command_applet(&command) {
-                                    Ok(x) => x,
-                                    Err(_) => response = 33,
-                                }
-                            } else {
-                                response = 34;
-                            }
-                        }
+                None => response = 36,
+            }
+        }
+        // Create applet (covering or preempting lower priority applets)
+        Opcode::CreateApplet => match create_applet(&command, stream_id) {
+            Ok((new_applet, session)) => {
+                let placed = place_applet(
+                    applet,
+                    location,
+                    carousels,
+                    new_applet,
+                    stream_id,
+                    options.preemption,
+                );
+                match placed {
+                    true => {
+                        // Only allow stream to modify its own applets
+                        owned.push(location);
+                        token = session;
                     }
-                    // Create applet
-                    None => {
-                        if command.opcode == Opcode::CreateApplet && command.parameters.len() == 1 {
-                            // Only allow stream to modify its own applet
-                            app_num = Some(command.app_num);
-                            match command.parameters[0] {
-                                0 => *applet = Some(Applet::new(Separator::Empty)),
-                                1 => *applet = Some(Applet::new(Separator::Solid)),
-                                2 => *applet = Some(Applet::new(Separator::Dotted)),
-                                3 => *applet = Some(Applet::new(Separator::Variable)),
-                                _ => response = 40,
-                            };
-                        }
+                    false => response = 34,
+                }
+            }
+            Err(x) => response = x,
+        },
+        // Write command to the stream's applet (even if covered or waiting)
+        _ => match find_owned(applet, location, carousels, stream_id) {
+            // Orphaned applets can only be resumed or replaced
+            Some((x, _)) if x.is_orphaned() => response = 31,
+            // A higher priority applet has preempted it
+            Some((x, true)) if x.is_preempted() => response = 37,
+            Some(_) if location.1 == 0 && command.opcode == Opcode::UpdateGrid => {
+                response = 32;
+            }
+            Some((x, covered)) => {
+                // The applet that preempted it has been removed
+                x.set_preempted(false);
+                match x.applet.command_applet(&command) {
+                    Ok(_) if covered => response = 2,
+                    Ok(_) => (),
+                    Err(e) => {
+                        response = 33;
+                        message = Some(e);
                     }
-                };
+                }
             }
+            // The carousel it waited in was preempted
+            None => {
+                owned.retain(|x| *x != location);
+                response = 37;
+            }
+        },
+    };
 
-            // Send response from previous match
-            match response {
-                // Finish command successfully
-                0 => {
-                    stream.write_u8(0).await.unwrap();
-                }
-                // Attempt to update applet 0 grid
-                32 => {
-                    eprintln!("Attempted to update applet 0 grid");
-                    stream.write_u8(32).await.unwrap();
-                    stop_applet(applets_mutex, app_num);
-                    panic!();
-                }
-                // Applet command error
-                33 => {
-                    eprintln!("Command failed");
-                    stream.write_u8(33).await.unwrap();
-                    continue;
-                }
-                // Attempt to create applet on top of another
-                34 => {
-                    eprintln!(
-                        "Attempted to generate new applet on existing applet {}",
-                        command.app_num
-                    );
-                    stream.write_u8(34).await.unwrap();
-                    stop_applet(applets_mutex, app_num);
-                    panic!();
-                }
-                // Invalid separator parameter
-                40 => {
-                    eprintln!("Invalid separator value: {client_addr}");
-                    stream.write_u8(40).await.unwrap();
-                    stop_applet(applets_mutex, app_num);
-                    panic!();
-                }
-                // Unknown error (should never be reached)
-                _ => {
-                    eprintln!("Unkown Error!");
-                    stream.write_u8(255).await.unwrap();
-                    exit(1);
-                }
-            };
+    // Warn JSON streams when a matrix of the command's screen is
+    // disconnected (state is still kept, so the command succeeds)
+    let disconnected = {
+        let screen = screens.iter().find(|x| x.contains(&location.0));
+        !screen.is_some_and(|x| x.iter().all(|y| connected.get(*y) == Some(&true)))
+    };
+
+    // Reply from previous match (true closes the stream)
+    match response {
+        // Finish command successfully (2 if the applet is covered)
+        0 | 2 => {
+            // Chosen slot and session token follow a successful
+            // CreateApplet
+            let mut success = Response::new(response, None);
+            success.app_num = assigned;
+            success.session = token;
+            success.disconnected = disconnected;
+            success.request_id = command.id;
+            (Reply::Response(success), false)
+        }
+        // Attempt to modify orphaned applet
+        31 => {
+            eprintln!("{client_addr} attempted to modify orphaned applet");
+            (error_reply(&command, 31, None), true)
+        }
+        // Attempt to update applet 0 grid
+        32 => {
+            eprintln!("Attempted to update applet 0 grid");
+            (error_reply(&command, 32, None), true)
+        }
+        // Applet command error
+        33 => {
+            eprintln!("Command failed: {}", message.as_deref().unwrap_or_default());
+            (error_reply(&command, 33, message), false)
+        }
+        // Attempt to create applet on top of another (streams that let the
+        // board choose may try again)
+        34 => {
+            eprintln!(
+                "Attempted to generate new applet on existing applet {}",
+                command.app_num
+            );
+            (error_reply(&command, 34, None), assigned.is_none())
+        }
+        // Invalid or expired session token
+        36 => {
+            eprintln!("Invalid session token: {client_addr}");
+            (error_reply(&command, 36, None), false)
+        }
+        // Applet is preempted by a higher priority applet
+        37 => {
+            eprintln!("{client_addr} attempted to modify preempted applet");
+            (error_reply(&command, 37, None), false)
+        }
+        // Invalid separator parameter (or wrong number of parameters)
+        40 => {
+            eprintln!("Invalid separator value: {client_addr}");
+            (error_reply(&command, 40, None), false)
+        }
+        // Invalid orientation parameter
+        41 => {
+            eprintln!("Invalid orientation value: {client_addr}");
+            (error_reply(&command, 41, None), false)
+        }
+        // Unknown error (should never be reached)
+        _ => {
+            eprintln!("Unkown Error!");
+            (error_reply(&command, 255, None), true)
         }
     }
 }
 
-/// Resets applet
-fn stop_applet(applets_mutex: Arc<Mutex<[Option<Applet>; 4]>>, app_num: Option<u8>) {
-    match app_num {
+/// Removes every complete JSON value from the start of the read data (a value
+/// may span reads, and braces inside strings do not end it)
+///
+/// Invalid JSON is returned as an error and the rest of the data is dropped
+pub fn take_values(read_data: &mut Vec<u8>) -> Vec<serde_json::Result<serde_json::Value>> {
+    let mut values = Vec::new();
+    let mut stream = serde_json::Deserializer::from_slice(read_data).into_iter();
+    let consumed = loop {
+        match stream.next() {
+            Some(Ok(x)) => values.push(Ok(x)),
+            // Incomplete value (wait for the rest)
+            Some(Err(e)) if e.is_eof() => break stream.byte_offset(),
+            Some(Err(e)) => {
+                values.push(Err(e));
+                break read_data.len();
+            }
+            None => break stream.byte_offset(),
+        }
+    };
+    read_data.drain(..consumed);
+    values
+}
+
+/// Writes an error code in the stream's response format (only JSON responses
+/// echo the command id)
+async fn respond(
+    stream: &mut TcpStream,
+    format: ResponseFormat,
+    id: Option<u64>,
+    code: u8,
+    message: Option<String>,
+) {
+    let mut response = Response::new(code, message);
+    response.request_id = id;
+    let data = reply_bytes(&Reply::Response(response), format);
+    stream.write_all(&data).await.unwrap();
+}
+
+/// Encodes a reply in the stream's response format (bytes are the code, then
+/// the chosen slot and session token if there are any, and Hello is always a
+/// JSON line)
+pub fn reply_bytes(reply: &Reply, format: ResponseFormat) -> Vec<u8> {
+    let mut data = match (reply, format) {
+        (Reply::Silent, _) => return Vec::new(),
+        (Reply::Response(response), ResponseFormat::Byte) => {
+            let mut data = vec![response.code];
+            if let Some(x) = response.app_num {
+                data.push(x);
+            }
+            if let Some(x) = response.session {
+                data.extend_from_slice(&x.to_be_bytes());
+            }
+            return data;
+        }
+        (Reply::Response(response), ResponseFormat::Json) => serde_json::to_vec(response),
+        (Reply::Hello(hello), _) => serde_json::to_vec(hello),
+    }
+    .unwrap();
+    data.push(b'\n');
+    data
+}
+
+/// Describes the board to a Hello command (code 42 if its version is not
+/// supported) and the encoding the stream's following responses use
+fn hello_response(
+    command: &Command,
+    screens: &[Vec<usize>],
+    connected: &[bool],
+    session_grace: Duration,
+    encoding: ResponseFormat,
+) -> HelloResponse {
+    let matrices: Vec<u8> = screens.iter().map(|x| x.len() as u8).collect();
+    let disconnected = (0..screens.len() as u8)
+        .filter(|x| screens[*x as usize].iter().any(|y| !connected[*y]))
+        .collect();
+    let widest = matrices.iter().copied().max().unwrap_or(0);
+    HelloResponse {
+        code: if command.supported_version() { 0 } else { 42 },
+        version: PROTOCOL_VERSION,
+        min_version: MIN_PROTOCOL_VERSION,
+        opcodes: Opcode::ALL.to_vec(),
+        screens: screens.len() as u8,
+        slots: 4 * widest,
+        width: 9 * matrices.iter().map(|x| *x as u16).sum::<u16>(),
+        height: 34,
+        session_grace: session_grace.as_secs(),
+        matrices,
+        disconnected,
+        encoding,
+        max_parameters: MAX_PARAMETERS as u16,
+        buffer_size: BUFFER_SIZE as u32,
+        request_id: command.id,
+    }
+}
+
+/// Whether a slot is empty or holds an orphaned applet that can no longer be
+/// resumed (applets with a session and restored applets are kept for the
+/// session grace period)
+pub fn replaceable(applet: &Option<BoardApplet>, session_grace: Duration) -> bool {
+    applet.as_ref().is_none_or(|x| {
+        x.is_orphaned()
+            && !((x.applet.session().is_some() || x.is_restored())
+                && x.orphaned_at().is_some_and(|t| t.elapsed() < session_grace))
+    })
+}
+
+/// Slot names used unless -n is given
+pub const DEFAULT_SLOT_NAMES: &str = "bar=0,top=1,middle=2,bottom=3";
+
+/// Converts slot names ("<name>=<slot>,...") to names and slots 0-3
+pub fn parse_slot_names(value: &str) -> Option<Vec<(String, u8)>> {
+    let mut names: Vec<(String, u8)> = Vec::new();
+    for pair in value.split(',') {
+        let (name, slot) = pair.split_once('=')?;
+        let slot = slot.parse::<u8>().ok().filter(|x| *x < 4)?;
+        // Names must be unique and can not be mistaken for slot numbers
+        if name.is_empty() || name.parse::<u8>().is_ok() || names.iter().any(|x| x.0 == name) {
+            return None;
+        }
+        names.push((name.to_string(), slot));
+    }
+    Some(names)
+}
+
+/// Converts a slot name to a slot, following how the matrix is mounted (grid
+/// slots count from the bottom if it is upside down)
+fn named_slot(name: &str, names: &[(String, u8)], orientation: Orientation) -> Option<u8> {
+    let flipped = matches!(
+        orientation,
+        Orientation::Rotate180 | Orientation::MirrorVertical
+    );
+    match names.iter().find(|x| x.0 == name)?.1 {
+        0 => Some(0),
+        x if flipped => Some(4 - x),
+        x => Some(x),
+    }
+}
+
+/// Chooses the app_num of a CreateApplet command that allocates its slot (Err
+/// is 30 for an unknown slot name and 34 if no slot is free)
+fn allocate_slot(
+    command: &Command,
+    applets: &[[Option<BoardApplet>; 4]],
+    carousels: &Carousels,
+    options: &Options,
+) -> Result<u8, u8> {
+    // Invalid screens are reported when locating the applet
+    let matrices = match options.screens.get(command.screen as usize) {
+        Some(x) => x,
+        None => return Ok(command.app_num),
+    };
+
+    // Named slots are within the matrix of the screen app_num refers to
+    let preferred = match &command.slot {
+        Some(name) => {
+            let slot = named_slot(name, &options.slot_names, options.orientation).ok_or(30)?;
+            command.app_num / 4 * 4 + slot
+        }
+        None => command.app_num,
+    };
+
+    // Grid slots that could be chosen, in order
+    let candidates: Vec<u8> = (0..matrices.len() as u8)
+        .flat_map(|x| (1..4).map(move |y| x * 4 + y))
+        .collect();
+    let location = |app_num: u8| Some((*matrices.get(app_num as usize / 4)?, app_num as usize % 4));
+    let slot = |app_num: u8| {
+        let (matrix, slot) = location(app_num)?;
+        applets.get(matrix)?.get(slot)
+    };
+    let free = |app_num: u8| slot(app_num).is_some_and(|x| replaceable(x, options.session_grace));
+    // Without a free slot, higher priority applets take the lowest priority one
+    let lowest = || {
+        candidates
+            .iter()
+            .filter_map(|&x| Some((slot(x)?.as_ref()?.applet.priority(), x)))
+            .filter(|(priority, _)| *priority < command.priority)
+            .min()
+            .map(|(_, x)| x)
+            .filter(|_| options.preemption != Preemption::None)
+    };
+    // Without a free slot, carousel applets join the shortest carousel
+    let shortest = || {
+        candidates
+            .iter()
+            .filter_map(|&x| {
+                let shown = slot(x)?.as_ref()?;
+                let waiting = carousels.waiting(location(x)?);
+                (shown.applet.is_carousel() && !shown.is_orphaned()).then_some((waiting, x))
+            })
+            .min()
+            .map(|(_, x)| x)
+            .filter(|_| command.carousel)
+    };
+    let first_free = || {
+        candidates
+            .iter()
+            .copied()
+            .find(|x| free(*x))
+            .or_else(shortest)
+            .or_else(lowest)
+            .ok_or(34)
+    };
+
+    match command.allocate {
+        Allocation::Fixed => Ok(preferred),
+        // The status bar can only be replaced by another status bar
+        Allocation::Preferred if free(preferred) || preferred % 4 == 0 => Ok(preferred),
+        Allocation::Preferred | Allocation::FirstFree => first_free(),
+    }
+}
+
+/// Finds the (matrix, applet) a command refers to or the error code if none
+fn locate(command: &Command, screens: &[Vec<usize>]) -> Result<(usize, usize), u8> {
+    let matrices = screens.get(command.screen as usize).ok_or(35)?;
+    let matrix = matrices.get(command.app_num as usize / 4).ok_or(30)?;
+    Ok((*matrix, command.app_num as usize % 4))
+}
+
+/// Creates a session token that is hard to guess
+fn new_session_token() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+/// Builds the applet a CreateApplet command describes and its session token
+/// (Err is the response code)
+fn create_applet(command: &Command, owner: u64) -> Result<(BoardApplet, Option<u64>), u8> {
+    let (separator, orientation) = command.creation().ok_or(40)?;
+    let mut applet = Applet::new(separator);
+    // Optional applet orientation
+    if let Some(value) = orientation {
+        applet.set_orientation(Orientation::from_u8(value).ok_or(41)?);
+    }
+    applet.set_on_disconnect(command.on_disconnect);
+    applet.set_priority(command.priority);
+    applet.set_carousel(command.carousel, command.dwell);
+    applet.set_transition(command.transition, command.transition_frames);
+    // Optional session token to resume the applet with
+    let token = command.session.then(new_session_token);
+    applet.set_session(token);
+    Ok((BoardApplet::new(applet, owner), token))
+}
+
+/// Puts a new applet in a slot, joining the slot's carousel or covering or
+/// preempting a lower priority applet if the policy allows (false if the slot
+/// is taken)
+///
+/// Lowest priority applets are always covered (whatever the policy)
+fn place_applet(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+    mut new_applet: BoardApplet,
+    owner: u64,
+    preemption: Preemption,
+) -> bool {
+    let mut old = match slot.take() {
+        Some(x) => x,
+        None => {
+            *slot = Some(new_applet);
+            return true;
+        }
+    };
+    // Streams can not cover their own applets (or share a carousel with them)
+    let available = !old.is_orphaned()
+        && old.find_owned(owner).is_none()
+        && carousels.find_owned(location, owner).is_none();
+    if available && old.is_lowest_priority() && !new_applet.is_lowest_priority() {
+        new_applet.cover(old);
+        *slot = Some(new_applet);
+        return true;
+    }
+    if available && new_applet.applet.is_carousel() && old.applet.is_carousel() {
+        carousels.join(location, old.id(), new_applet, Instant::now());
+        *slot = Some(old);
+        return true;
+    }
+    let lower = available && new_applet.applet.priority() > old.applet.priority();
+    match preemption {
+        Preemption::Cover if lower => new_applet.cover(old),
+        Preemption::Preempt if lower => {
+            // The applets waiting in a preempted applet's carousel are removed
+            // (only it is shown again)
+            if carousels.shown(location) == Some(old.id()) {
+                carousels.clear(location);
+            }
+            old.set_preempted(true);
+            new_applet.cover(old);
+        }
+        _ => {
+            *slot = Some(old);
+            return false;
+        }
+    }
+    *slot = Some(new_applet);
+    true
+}
+
+/// Takes a grid slot for a built-in applet the way a stream's CreateApplet
+/// would (Err says why no slot was taken)
+fn create_builtin(
+    mut command: Command,
+    owner: u64,
+    applets_mutex: &Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: &Arc<Mutex<Carousels>>,
+    options: &Arc<Mutex<Options>>,
+) -> Result<(usize, usize), &'static str> {
+    let options = options.try_lock().unwrap();
+    let mut applets = applets_mutex.try_lock().unwrap();
+    let mut carousels = carousels.try_lock().unwrap();
+    if command.allocates() {
+        command.app_num =
+            allocate_slot(&command, &applets, &carousels, &options).map_err(|_| "No free slot")?;
+    }
+    let location = match locate(&command, &options.screens) {
+        Ok(x) if x.1 != 0 => x,
+        _ => return Err("Invalid slot"),
+    };
+    // Orphaned applets are replaced (unless their session can be resumed)
+    let slot = &mut applets[location.0][location.1];
+    remove_replaceable(slot, location, &mut carousels, options.session_grace);
+    let (applet, _) = create_applet(&command, owner).map_err(|_| "Invalid applet")?;
+    match place_applet(
+        slot,
+        location,
+        &mut carousels,
+        applet,
+        owner,
+        options.preemption,
+    ) {
+        true => Ok(location),
+        false => Err("Slot is taken"),
+    }
+}
+
+/// Writes commands to a built-in applet, even while it is covered (false once
+/// it has been removed)
+fn command_builtin(
+    applets_mutex: &Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: &Arc<Mutex<Carousels>>,
+    location: (usize, usize),
+    owner: u64,
+    commands: &[Command],
+) -> bool {
+    let mut applets = applets_mutex.try_lock().unwrap();
+    let mut carousels = carousels.try_lock().unwrap();
+    let slot = &mut applets[location.0][location.1];
+    let applet = match find_owned(slot, location, &mut carousels, owner) {
+        Some((x, _)) => x,
+        None => return false,
+    };
+    for command in commands {
+        if let Err(e) = applet.applet.command_applet(command) {
+            eprintln!("Built-in applet command failed: {e}");
+        }
+    }
+    true
+}
+
+/// Reattaches an orphaned applet in a slot (shown, waiting or covered) to a
+/// stream if the session token (8 u8) matches, returning whether it is covered
+fn resume_applet(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+    parameters: &[u8],
+    owner: u64,
+) -> Option<bool> {
+    let token = u64::from_be_bytes(<[u8; 8]>::try_from(parameters).ok()?);
+    // Applets waiting in a carousel are not covered
+    let (applet, covered) = match carousels.find_session(location, token) {
+        Some(x) => (x, false),
+        None => slot.as_mut()?.find_session(token)?,
+    };
+    applet.set_orphaned(false);
+    applet.set_owner(owner);
+    Some(covered)
+}
+
+/// Takes the notices waiting for a stream's applets, ending the preemption of
+/// those shown again (the applet that preempted them has been removed)
+pub fn take_notices(
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    state: &StreamState,
+    screens: &[Vec<usize>],
+) -> Vec<Response> {
+    let mut notices = Vec::new();
+    for &location in &state.owned {
+        let slot = match applets.get_mut(location.0) {
+            Some(x) => &mut x[location.1],
+            None => continue,
+        };
+        let applet = match find_owned(slot, location, carousels, state.id) {
+            Some((x, covered)) if x.is_preempted() && covered => x,
+            Some((x, _)) => {
+                x.set_preempted(false);
+                x
+            }
+            None => continue,
+        };
+        if let Some(code) = applet.take_notice() {
+            let mut notice = Response::new(code, None);
+            notice.app_num = app_num(location, screens);
+            notice.notice = true;
+            notices.push(notice);
+        }
+    }
+    notices
+}
+
+/// Applet number (within its screen) of a (matrix, applet) location
+fn app_num(location: (usize, usize), screens: &[Vec<usize>]) -> Option<u8> {
+    let index = screens
+        .iter()
+        .find_map(|x| x.iter().position(|y| *y == location.0))?;
+    Some((index * 4 + location.1) as u8)
+}
+
+/// Finds the applet of a slot (shown, waiting in its carousel or covered)
+/// owned by a stream and whether it is covered
+fn find_owned<'a>(
+    slot: &'a mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &'a mut Carousels,
+    owner: u64,
+) -> Option<(&'a mut BoardApplet, bool)> {
+    match carousels.find_owned(location, owner) {
+        Some(x) => Some((x, false)),
+        None => slot.as_mut()?.find_owned(owner),
+    }
+}
+
+/// Removes the shown applet of a slot, showing the next carousel applet or the
+/// applet it covered in its place
+fn remove_shown(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+) {
+    let id = match slot {
+        Some(x) => x.id(),
+        None => return,
+    };
+    if carousels
+        .pass_turn(slot, location, id, Instant::now())
+        .is_none()
+    {
+        *slot = slot.take().and_then(|mut x| x.take_covered());
+    }
+}
+
+/// Removes orphaned applets that can no longer be resumed from a slot (the
+/// applets they covered are shown again)
+fn remove_replaceable(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+    session_grace: Duration,
+) {
+    while slot.is_some() && replaceable(slot, session_grace) {
+        remove_shown(slot, location, carousels);
+    }
+}
+
+/// How long an applet is kept after its stream disconnects (None is forever,
+/// applets with a session are kept at least 'session_grace' to be resumed)
+fn keep_time(applet: &BoardApplet, session_grace: Duration) -> Option<Duration> {
+    let grace = match applet.applet.session() {
+        Some(_) => session_grace,
+        None => Duration::ZERO,
+    };
+    match applet.applet.on_disconnect() {
+        OrphanPolicy::Clear => Some(grace),
+        // Faded applets stay (blank) until their session can not be resumed
+        OrphanPolicy::Keep(seconds) | OrphanPolicy::Fade(seconds) => {
+            Some(grace.max(Duration::from_secs(seconds as u64)))
+        }
+        OrphanPolicy::KeepForever => None,
+    }
+}
+
+/// Removes a stream's applet (with an id) from a slot, whether it is shown,
+/// waiting in a carousel or covered
+fn remove_owned(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+    owner: u64,
+    id: u64,
+) {
+    // Waiting carousel applets leave the carousel
+    if carousels.remove_owned(location, owner).is_some() {
+        return;
+    }
+    // Carousel applets give the next one their turn
+    if carousels
+        .pass_turn(slot, location, id, Instant::now())
+        .is_some()
+    {
+        return;
+    }
+    match slot {
+        // Covering applets show the applet they covered again
+        Some(x) if x.id() == id => {
+            let below = x.take_covered();
+            *slot = below;
+        }
         Some(x) => {
-            if x > 3 {
-                eprintln!("stop_applet recieved invalid app_num: {x}")
-            };
-            applets_mutex.try_lock().unwrap()[x as usize] = None
+            x.remove_covered(owner);
         }
         None => (),
     }
 }
 
+/// Resets a stream's applets according to their disconnect policies, whether
+/// they are shown, waiting in a carousel or covered (kept applets are orphaned
+/// where they are and can be resumed there)
+pub fn stop_applet(
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    app_nums: &[(usize, usize)],
+    owner: u64,
+    session_grace: Duration,
+) {
+    for &(screen, applet) in app_nums {
+        let location = (screen, applet);
+        let slot = match applets.get_mut(screen) {
+            Some(x) if applet < 4 => &mut x[applet],
+            _ => {
+                eprintln!("stop_applet recieved invalid app_num: {applet} (screen {screen})");
+                continue;
+            }
+        };
+        let (id, keep) = match find_owned(slot, location, carousels, owner) {
+            Some((x, _)) => (x.id(), keep_time(x, session_grace)),
+            None => continue,
+        };
+        match keep {
+            Some(keep) if keep.is_zero() => remove_owned(slot, location, carousels, owner, id),
+            _ => {
+                if let Some((x, _)) = find_owned(slot, location, carousels, owner) {
+                    x.set_orphaned(true);
+                    x.set_expires(keep.map(|keep| Instant::now() + keep));
+                }
+            }
+        }
+    }
+}
+
+/// Combines every applet's region (separator and grid) into a single matrix
+/// in the given orientation
+pub fn compose_regions(regions: &[[[u8; 9]; 11]; 4], orientation: Orientation) -> [[u8; 9]; 34] {
+    let mut board_input = [[0; 9]; 34];
+
+    // Copy stored data to board_input
+    board_input[0] = regions[0][0];
+    for i in 1..4 {
+        board_input[11 * (i - 1) + 1..11 * i + 1].copy_from_slice(&regions[i]);
+    }
+
+    // Orient the whole matrix to match how the module is mounted
+    orientation.apply(&board_input)
+}
+
+/// The matrices of one screen side by side, drawn as a single image
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Canvas {
+    /// LED columns (9 per matrix)
+    pub width: usize,
+    /// Row-major brightnesses of the 34 rows
+    pub pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// Places matrices side by side (left to right) and orients the whole
+    /// canvas, so horizontal flips also swap the order of the matrices
+    pub fn new(matrices: &[[[u8; 9]; 34]], orientation: Orientation) -> Self {
+        let width = 9 * matrices.len();
+        let mut pixels = vec![0; width * 34];
+        for i in 0..34 {
+            for j in 0..width {
+                let (x, y) = orientation.source(i, j, width, 34);
+                pixels[i * width + j] = matrices[y / 9][x][y % 9];
+            }
+        }
+        Canvas { width, pixels }
+    }
+
+    /// Splits the canvas back into its matrices (left to right)
+    pub fn matrices(&self) -> Vec<[[u8; 9]; 34]> {
+        let mut matrices = vec![[[0; 9]; 34]; self.width / 9];
+        for (i, row) in self.pixels.chunks(self.width).enumerate() {
+            for (matrix, values) in matrices.iter_mut().zip(row.chunks(9)) {
+                matrix[i].copy_from_slice(values);
+            }
+        }
+        matrices
+    }
+}
+
+/// When a frame is drawn
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTime {
+    /// Frames written before this one (moves temporal dithering)
+    pub frame: u64,
+    /// Animation ticks since the board started
+    pub tick: u64,
+    /// Time the frame is drawn at (fades orphaned applets)
+    pub now: Instant,
+}
+
+/// Combines the applets of each screen's matrices into one canvas per screen
+/// for a frame, rendering transitions between the applets shown in each slot
+pub fn compose_screens(
+    applets: &[[Option<BoardApplet>; 4]],
+    screens: &[Vec<usize>],
+    orientation: Orientation,
+    transitions: &mut transition::Transitions,
+    time: FrameTime,
+) -> Vec<Canvas> {
+    let matrices: Vec<[[u8; 9]; 34]> = applets
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let regions = transitions.render(i, x, time.tick, time.now);
+            compose_regions(&regions, Orientation::Normal)
+        })
+        .collect();
+    screens
+        .iter()
+        .map(|x| {
+            let screen: Vec<[[u8; 9]; 34]> = x.iter().map(|y| matrices[*y]).collect();
+            Canvas::new(&screen, orientation)
+        })
+        .collect()
+}
+
 /// Periodically writes entire LED matrix
 pub async fn write_board(
-    applets_mutex: Arc<Mutex<[Option<Applet>; 4]>>,
-    board: Arc<Mutex<matrix::LedMatrixInterface>>,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    boards: Arc<Mutex<Vec<device::Board>>>,
+    options: Arc<Mutex<Options>>,
+    resumed: Arc<AtomicBool>,
     write_interval: Duration,
 ) {
-    let mut board_input = [[0; 9]; 34];
+    let mut detector = resume::ResumeDetector::new(resume::RESUME_THRESHOLD);
+    let mut transitions = {
+        let options = options.try_lock().unwrap();
+        transition::Transitions::new(options.transition, options.transition_frames)
+    };
+    // Counts frames to move temporal dithering
+    let mut frame: u64 = 0;
+    // Animations move on every tick since the board started
+    let start = Instant::now();
+    let time = |frame| {
+        let now = Instant::now();
+        let tick = now.saturating_duration_since(start).as_millis() / TICK_INTERVAL.as_millis();
+        FrameTime {
+            frame,
+            tick: tick as u64,
+            now,
+        }
+    };
 
     // Only attempt pause if write_interval > 0
     if write_interval >= Duration::from_nanos(1) {
@@ -357,57 +1470,125 @@ pub async fn write_board(
         loop {
             // Wait for interval (regardless of time spent refreshing or handling requests)
             clock.tick().await;
-            // Copy stored data to board_input
-            let status_bar = match &applets_mutex.try_lock().unwrap()[0] {
-                Some(x) => x.get_board()[0],
-                None => [0; 9],
-            };
-            for i in 0..9 {
-                board_input[0][i] = status_bar[i];
-            }
-            for i in 1..4 {
-                let applet = match &applets_mutex.try_lock().unwrap()[i] {
-                    Some(x) => x.get_board(),
-                    None => [[0; 9]; 11],
-                };
-                {
-                    for j in 0..11 {
-                        for k in 0..9 {
-                            board_input[11 * (i - 1) + j + 1][k] = applet[j][k];
-                        }
-                    }
-                }
+            if detector.check() | resumed.swap(false, Ordering::SeqCst) {
+                reinit_boards(&boards);
             }
-            // Write board input to Framework LED matrix
-            pwm(&mut board.try_lock().unwrap(), &board_input);
+            write_screens(
+                &applets_mutex,
+                &carousels,
+                &boards,
+                &options,
+                &mut transitions,
+                time(frame),
+            );
+            frame = frame.wrapping_add(1);
         }
     } else {
         loop {
-            // Copy stored data to board_input
-            let status_bar = match &applets_mutex.try_lock().unwrap()[0] {
-                Some(x) => x.get_board()[0],
-                None => [0; 9],
-            };
-            for i in 0..9 {
-                board_input[0][i] = status_bar[i];
-            }
-            for i in 1..4 {
-                let applet = match &applets_mutex.try_lock().unwrap()[i] {
-                    Some(x) => x.get_board(),
-                    None => [[0; 9]; 11],
-                };
+            if detector.check() | resumed.swap(false, Ordering::SeqCst) {
+                reinit_boards(&boards);
+            }
+            write_screens(
+                &applets_mutex,
+                &carousels,
+                &boards,
+                &options,
+                &mut transitions,
+                time(frame),
+            );
+            frame = frame.wrapping_add(1);
+        }
+    }
+}
+
+/// Removes orphaned applets whose time has run out (shown, waiting or
+/// covered)
+pub fn expire_orphans(
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    now: Instant,
+) {
+    carousels.remove_expired(now);
+    for (matrix, screen_applets) in applets.iter_mut().enumerate() {
+        for (i, slot) in screen_applets.iter_mut().enumerate() {
+            let location = (matrix, i);
+            // A covered carousel applet gives the next one its turn
+            if let Some(id) = carousels.shown(location) {
+                if slot
+                    .as_mut()
+                    .and_then(|x| x.find_id(id))
+                    .is_some_and(|x| x.expired(now))
                 {
-                    for j in 0..11 {
-                        for k in 0..9 {
-                            board_input[11 * (i - 1) + j + 1][k] = applet[j][k];
-                        }
-                    }
+                    carousels.pass_turn(slot, location, id, now);
                 }
             }
-            // Write board input to Framework LED matrix
-            pwm(&mut board.try_lock().unwrap(), &board_input);
+            while slot.as_ref().is_some_and(|x| x.expired(now)) {
+                remove_shown(slot, location, carousels);
+            }
+            if let Some(x) = slot {
+                x.remove_expired(now);
+            }
+        }
+    }
+}
+
+/// Reinitializes every matrix after a system resume
+fn reinit_boards(boards: &Arc<Mutex<Vec<device::Board>>>) {
+    println!("Reinitializing LED matrices after resume");
+    for board in boards.try_lock().unwrap().iter_mut() {
+        board.reinit();
+    }
+}
+
+/// Writes the current applets to every Framework LED matrix
+fn write_screens(
+    applets_mutex: &Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: &Arc<Mutex<Carousels>>,
+    boards: &Arc<Mutex<Vec<device::Board>>>,
+    options: &Arc<Mutex<Options>>,
+    transitions: &mut transition::Transitions,
+    time: FrameTime,
+) {
+    let (screens, orientation, dwell, reduce) = {
+        let options = options.try_lock().unwrap();
+        (
+            options.screens.clone(),
+            options.orientation,
+            options.dwell,
+            options.dither,
+        )
+    };
+    let mut applets = applets_mutex.try_lock().unwrap();
+    let mut carousels = carousels.try_lock().unwrap();
+    expire_orphans(&mut applets, &mut carousels, time.now);
+    carousels.rotate(&mut applets, dwell, time.now);
+    let canvases = render_screens(&applets, &screens, orientation, transitions, reduce, time);
+    let mut boards = boards.try_lock().unwrap();
+    for (matrices, canvas) in screens.iter().zip(canvases.iter()) {
+        for (matrix, board_input) in matrices.iter().zip(canvas.matrices().iter()) {
+            boards[*matrix].write_frame(board_input);
+        }
+    }
+}
+
+/// Composes each screen's canvas for a frame, reduced to a number of
+/// brightness levels if a dither is given (frames pass unchanged otherwise)
+pub fn render_screens(
+    applets: &[[Option<BoardApplet>; 4]],
+    screens: &[Vec<usize>],
+    orientation: Orientation,
+    transitions: &mut transition::Transitions,
+    reduce: Option<(Dither, u8)>,
+    time: FrameTime,
+) -> Vec<Canvas> {
+    let mut canvases = compose_screens(applets, screens, orientation, transitions, time);
+    // Dithering spreads across the whole canvas
+    if let Some((x, levels)) = reduce {
+        for canvas in canvases.iter_mut() {
+            dither(&mut canvas.pixels, canvas.width, levels, x, time.frame);
         }
     }
+    canvases
 }
 
 /// Give error message and exit
@@ -16,15 +16,19 @@
 //! {
 //!     "opcode": "<Command Name>",
 //!     "app_num": <Applet Number (0-2)>,
+//!     "screen": <Screen Number> (optional, default 0),
 //!     "parameters": [x<,y<,...z> (where each value is a u8)]
 //! }
 //! ```
 //!
+//! A stream may create (or resume) several applets. Commands to applets the
+//! stream did not create are rejected with error 31.
+//!
 //! Commands:
 //!
 //! CreateApplet - Creates a new applet assigned to the requesting TCP stream
 //!
-//! Parameters: 1 u8 from 0-3
+//! Parameters: 1 u8 from 0-8
 //! ```text
 //!     0 - Applet separator is empty (all LED's off)
 //!
@@ -33,66 +37,260 @@
 //!     2 - Applet separator is dotted (alternating LED's on & off)
 //!
 //!     3 - Applet seprator is variable (default off)
+//!
+//!     4 - Applet separator is dashed
+//!
+//!     5 - Applet separator is a single center dot
+//!
+//!     6 - Applet separator is a gradient (dark to bright)
+//!
+//!     7 - Applet separator is a progress bar (default empty)
+//!
+//!     8 - Applet separator is animated marching ants
+//! ```
+//!
+//! Optional "separator" field: "Empty", "Solid", "Dotted", "Variable",
+//! "Dashed", "CenterDot", "Gradient", "Progress" or "MarchingAnts" replaces the
+//! 1st parameter (the orientation is then the only parameter)
+//!
+//! Optional 2nd parameter: 1 u8 from 0-3 (applet grid orientation)
+//! ```text
+//!     0 - Normal
+//!
+//!     1 - Rotated 180 degrees
+//!
+//!     2 - Mirrored horizontally
+//!
+//!     3 - Mirrored vertically
+//! ```
+//!
+//! Optional "allocate" field: lets the board choose the applet's slot. The
+//! chosen app_num follows the response code as 1 u8 (before any session token)
+//! ```text
+//!     "Fixed" - Exactly app_num (default)
+//!
+//!     "FirstFree" - The first free grid slot (app_num is ignored)
+//!
+//!     "Preferred" - app_num, or the first free grid slot if it is taken
+//! ```
+//!
+//! Optional "slot" field: names the slot instead of app_num - "bar", "top",
+//! "middle" or "bottom" (or the names given with -n) as the matrix is mounted
+//! (-o). Named slots also respond with the chosen app_num. An unknown name
+//! returns error 30 and no free slot returns error 34 (the stream stays open)
+//!
+//! Optional "session": true field: the response code is followed by an 8 byte
+//! (big-endian) session token for Resume
+//!
+//! Optional "priority" field: a u8 (default 0). With -c cover or -c preempt a
+//! CreateApplet for a slot taken by a lower priority applet succeeds instead of
+//! returning error 34
+//! ```text
+//!     cover - The lower priority applet is hidden (its commands return 2) and
+//!     shown again once the covering applet is removed (its on_disconnect
+//!     policy and session apply when its stream disconnects). A covered
+//!     applet's stream may disconnect and resume the same way
+//!
+//!     preempt - The lower priority applet is hidden and its commands return
+//!     37 until the preempting applet is removed, when it is shown again.
+//!     Streams using JSON responses are sent a notice, a response with
+//!     "notice": true and the applet's "app_num", with code 37 when their
+//!     applet is preempted and 38 when it is shown again (byte streams only
+//!     see the 37s). Applets waiting in a preempted applet's carousel are
+//!     removed (their commands return 37)
+//! ```
+//!
+//! Optional "carousel": true field: a CreateApplet for a slot taken by another
+//! carousel applet joins the slot's carousel instead of returning error 34.
+//! Carousel applets are shown in turn for 5 seconds each (set with -w, or per
+//! applet with a "dwell" field in seconds). Allocating carousel CreateApplets
+//! join the shortest carousel when no slot is free. Waiting and shown carousel
+//! applets follow their disconnect policy and session like other applets
+//!
+//! Optional "transition" field: the effect shown when the applet appears, is
+//! replaced (e.g. by the next carousel applet) or is removed, over
+//! "transition_frames" frames (default -e and -x, 15 frames)
+//! ```text
+//!     "None", "Fade", "WipeUp", "WipeDown", "Slide" or "Dissolve"
+//! ```
+//!
+//! Optional "on_disconnect" field: what happens to the applet when its stream
+//! disconnects
+//! ```text
+//!     "Clear" - Applet is removed (default)
+//!
+//!     {"Keep": N} - Last frame is kept for N seconds
+//!
+//!     "KeepForever" - Last frame is kept until another applet replaces it
+//!
+//!     {"Fade": N} - Last frame fades out over N seconds
 //! ```
 //!
 //! UpdateGrid - Rewrites the current 9x10 applet grid with new values
 //!
-//! Parameters: 
+//! Parameters:
 //!     90 u8 representing grid brightnesses - rows then columns (1st 10 is row1, 2nd 10 is row2, etc.)
 //!
 //! UpdateBar - Rewrites the current 9x1 applet separator
 //!
-//! Parameters: 
-//!     9 u8 representing separator brightnesses
+//! Parameters:
+//!     9 u8 representing separator brightnesses, or 1 u8 from 0-100 for a
+//!     progress bar separator
+//!
+//!     Note: Error 33 returned if bar is not variable or a progress bar
 //!
-//!     Note: Error 32 returned if bar is not variable
+//! SetSeparatorStyle - Changes the separator style of the applet (the
+//! separator is reset to the style's pattern)
 //!
+//! Parameters:
+//!     1 u8 from 0-8 (as in CreateApplet), or none with a "separator" field
 //!
-//! sig_rp2040_board will respond with a single u8 error code (not JSON):
+//! Resume - Reattaches a reconnecting stream to the applet it created with a
+//! session
 //!
-//! 0:	    Command successfully processed
+//! Parameters:
+//!     8 u8 session token (big-endian) returned by CreateApplet
 //!
-//! 10:	    Failed to read data from stream
+//!     Note: An applet with a session is kept for a grace period (-g) after its
+//!     stream disconnects and can not be replaced by other streams in that time.
+//!     Covered applets are resumed beneath the covering applet and return 2
 //!
-//! 20:	    Failed to parse stream data as UTF-8
+//! Hello - Checks that the board supports the client's protocol version
+//! (app_num may be left out)
 //!
-//! 21:	    Failed to parse stream data as JSON
+//! Parameters:
+//!     1 u8 protocol version of the client (currently 1)
 //!
-//! 30:	    Command uses invalid applet number (greater than 2)
+//!     Note: Always answered with one JSON line describing the board (version,
+//!     opcodes, screens, slots, width, height, session_grace, the matrices
+//!     making up each screen, the screens with a disconnected matrix, the
+//!     encoding of following responses, max_parameters and buffer_size). A
+//!     "response" field chooses the encoding. "code" is 42 and the stream is
+//!     closed if the version is not supported
 //!
-//! 31:	    Command attempts to modify applet stream did not create
+//!
+//! sig_rp2040_board will respond with a single u8 error code (not JSON), unless
+//! the stream has asked for JSON responses.
+//!
+//! Optional "response" field on any command: "Json" switches this and following
+//! responses on the stream to one JSON object per line, "Byte" switches back
+//! ```text
+//! {"code": 33, "name": "CommandFailed", "message": "Invalid parameter length: expected 90 got 89"}
+//! ```
+//! A successful CreateApplet with a session adds "session": <token> instead of
+//! writing 8 bytes. Successful commands add "disconnected": true while a matrix
+//! of their screen is disconnected (the applet is shown once it reconnects)
+//!
+//! Optional "id" field on any command: a u64 echoed as "request_id" in JSON
+//! responses, to tell which of several pipelined commands a response belongs to
+//!
+//! Optional "no_ack": true field on any command: the board does not respond
+//! (even if the command fails) unless it returns a chosen slot or session token,
+//! e.g. for high-rate UpdateGrid streams
+//!
+//! Response codes:
+//!
+//! 0:      Command successfully processed
+//!
+//! 2:      Command successfully processed but the applet is covered by a higher
+//!         priority applet
+//!
+//! 10:     Failed to read data from stream
+//!
+//! 20:     Failed to parse stream data as UTF-8
+//!
+//! 21:     Failed to parse stream data as JSON
+//!
+//! 30:     Command uses invalid applet number (greater than 2)
+//!
+//! 31:     Command attempts to modify applet stream did not create (or orphaned applet)
 //!
 //! 32:     Attempt to update applet 0 grid
 //!
-//! 33:	    Error in commanding applet
+//! 33:     Error in commanding applet
+//!
+//! 34:     Attempt to create new applet when applet already exists
 //!
-//! 34:	    Attempt to create new applet when applet already exists
+//! 35:     Command uses invalid screen number
 //!
-//! 40:	    Invalid separator value when creating applet
+//! 36:     Invalid or expired session token
 //!
-//! 255:	Unknown error
+//! 37:     Applet was preempted by a higher priority applet (also sent as a
+//!         notice)
+//!
+//! 38:     Preempted applet is shown again (only sent as a notice)
+//!
+//! 40:     Invalid separator value (or number of parameters) when creating applet
+//!
+//! 41:     Invalid orientation value when creating applet
+//!
+//! 42:     Unsupported protocol version (Hello)
+//!
+//! 255:    Unknown error
 
 use std::env;
 
 use std::time::Duration;
 
+use sig_rp2040_applet::Command;
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::carousel::Carousels;
 use sig_rp2040_matrix_board::*;
 
+use std::io;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::process::exit;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut options: Options = Options { test: false }; // [frame test, port, allow runtime replacement]
+
+    // Run as a one-shot client instead of a server
+    if args.get(1).is_some_and(|x| x == "send") {
+        exit(send::send(&args[2..]));
+    }
+    if args.get(1).is_some_and(|x| x == "image") {
+        exit(image::image(&args[2..]));
+    }
+    let mut options = Options::default();
+    let mut devices: Vec<(String, Option<Layout>)> = Vec::new();
+    let mut layout = Layout::Screens;
+    let mut state_file: Option<PathBuf> = None;
+    let mut bar_widgets: Vec<(widgets::Widget, u8)> = Vec::new();
+    let mut monitors: Vec<(sysmon::Monitor, Command)> = Vec::new();
+    let mut clock: Option<(clock::Clock, Command)> = None;
+    let mut sources = system::Sources::default();
     let mut port: u16 = 27072;
     let mut write_interval: Duration = Duration::from_millis(17);
+    let mut list_devices = false;
 
     // Collect user parameters
     let mut current_parameter = 1;
     while current_parameter < args.len() {
-        if args[current_parameter].starts_with("-") {
+        if args[current_parameter] == "--list-devices" {
+            list_devices = true;
+        } else if args[current_parameter] == "--device" {
+            if args.len() < current_parameter + 2 {
+                error_argument()
+            };
+            devices.push(parse_device(&args[current_parameter + 1]).expect("Invalid device"));
+            current_parameter += 1;
+        } else if args[current_parameter] == "--proc" {
+            if args.len() < current_parameter + 2 {
+                error_argument()
+            };
+            sources.proc = PathBuf::from(&args[current_parameter + 1]);
+            current_parameter += 1;
+        } else if args[current_parameter] == "--sys" {
+            if args.len() < current_parameter + 2 {
+                error_argument()
+            };
+            sources.sys = PathBuf::from(&args[current_parameter + 1]);
+            current_parameter += 1;
+        } else if args[current_parameter].starts_with("-") {
             for j in args[current_parameter][1..].chars() {
                 match j {
                     't' => options.test = true,
@@ -122,6 +320,174 @@ fn main() {
                         );
                         current_parameter += 1;
                     }
+                    'o' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.orientation = parse_orientation(&args[current_parameter + 1])
+                            .expect("Invalid orientation");
+                        current_parameter += 1;
+                    }
+                    'd' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        devices.push(
+                            parse_device(&args[current_parameter + 1]).expect("Invalid device"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'g' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.session_grace = Duration::from_secs(
+                            args[current_parameter + 1]
+                                .parse::<u64>()
+                                .expect("Invalid session grace period"),
+                        );
+                        current_parameter += 1;
+                    }
+                    's' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        state_file = Some(PathBuf::from(&args[current_parameter + 1]));
+                        current_parameter += 1;
+                    }
+                    'c' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.preemption = parse_preemption(&args[current_parameter + 1])
+                            .expect("Invalid preemption policy");
+                        current_parameter += 1;
+                    }
+                    'w' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.dwell = Duration::from_secs(
+                            args[current_parameter + 1]
+                                .parse::<u64>()
+                                .expect("Invalid carousel dwell time"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'e' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.transition = parse_transition(&args[current_parameter + 1])
+                            .expect("Invalid transition effect");
+                        current_parameter += 1;
+                    }
+                    'x' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.transition_frames = args[current_parameter + 1]
+                            .parse::<u16>()
+                            .expect("Invalid transition length");
+                        current_parameter += 1;
+                    }
+                    'q' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.dither = Some(
+                            parse_dither(&args[current_parameter + 1]).expect("Invalid dither"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'n' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        options.slot_names = parse_slot_names(&args[current_parameter + 1])
+                            .expect("Invalid slot names");
+                        current_parameter += 1;
+                    }
+                    'b' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        bar_widgets.push(
+                            widgets::parse_widget(&args[current_parameter + 1])
+                                .expect("Invalid status bar widget"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'm' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        monitors.push(
+                            sysmon::parse_monitor(&args[current_parameter + 1])
+                                .expect("Invalid system monitor"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'k' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        clock = Some(
+                            clock::parse_clock(&args[current_parameter + 1])
+                                .expect("Invalid clock"),
+                        );
+                        current_parameter += 1;
+                    }
+                    'l' => {
+                        if args[current_parameter].len() > 2 {
+                            error_argument()
+                        };
+                        if args.len() < current_parameter + 2 {
+                            error_argument()
+                        };
+                        layout =
+                            parse_layout(&args[current_parameter + 1]).expect("Invalid layout");
+                        current_parameter += 1;
+                    }
                     _ => {
                         println!("{HELP_PAGE}");
                         exit(0);
@@ -135,26 +501,69 @@ fn main() {
         current_parameter += 1;
     }
 
-    // Start connection to LED matrix
-    let mut board = matrix::LedMatrixInterface::new(BAUDRATE, TIMEOUT);
-    init(&mut board);
+    // Listing ports waits for --sys in case it follows
+    if list_devices {
+        device::print_devices(&sources.sys);
+        exit(0);
+    }
+
+    // Start connection to each LED matrix (the only connected one if none given)
+    let names: Vec<Option<&str>> = if devices.is_empty() {
+        vec![None]
+    } else {
+        devices.iter().map(|x| Some(x.0.as_str())).collect()
+    };
+    let layouts: Vec<Layout> = match devices.is_empty() {
+        true => vec![layout],
+        false => devices.iter().map(|x| x.1.unwrap_or(layout)).collect(),
+    };
+    options.screens = group_screens(&layouts);
+    let mut boards = Vec::with_capacity(names.len());
+    for name in names {
+        match device::open_matrix(name, &sources.sys, BAUDRATE, TIMEOUT) {
+            Ok(x) => boards.push(x),
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+        }
+    }
+    for board in boards.iter_mut() {
+        init(&mut board.interface);
+    }
 
-    // Run test of board if in that mode
-    if options.test == true {
-        frame_test(&mut board);
+    // Run test of boards if in that mode
+    if options.test {
+        for board in boards.iter_mut() {
+            frame_test(&mut board.interface);
+        }
         exit(0);
     }
 
-    // Wrap board array, applet array, and options in send safe mutexes
-    let board_mutex = Arc::new(Mutex::new(board));
-    let applets_mutex = Arc::new(Mutex::new([None, None, None, None]));
+    // Restore applets from the previous run if a state file is used
+    let mut applets: Vec<[Option<BoardApplet>; 4]> =
+        boards.iter().map(|_| [None, None, None, None]).collect();
+    let mut carousels = Carousels::default();
+    if let Some(path) = &state_file {
+        match state::load(path, boards.len()) {
+            Ok(x) => (applets, carousels) = x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => eprintln!("Failed to restore state file {}: {e}", path.display()),
+        }
+    }
+
+    // Wrap board array, applet array, carousels and options in send safe mutexes
+    let board_mutex = Arc::new(Mutex::new(boards));
+    let applets_mutex = Arc::new(Mutex::new(applets));
+    let carousels = Arc::new(Mutex::new(carousels));
     let options = Arc::new(Mutex::new(options));
+    let resumed = Arc::new(AtomicBool::new(false));
 
     // Define TCP server address
     let local_addr = SocketAddr::from(([127, 0, 0, 1], port));
 
     // Create list to store handles for async task
-    let mut task_handles = Vec::with_capacity(2);
+    let mut task_handles = Vec::with_capacity(3);
 
     // Create single threaded (congruent) runtime
     let rt = tokio::runtime::Builder::new_current_thread()
@@ -169,19 +578,64 @@ fn main() {
         task_handles.push(tokio::spawn(handle_streams(
             local_addr,
             Arc::clone(&applets_mutex),
+            Arc::clone(&carousels),
+            Arc::clone(&board_mutex),
             Arc::clone(&options),
         )));
         task_handles.push(tokio::spawn(write_board(
             Arc::clone(&applets_mutex),
+            Arc::clone(&carousels),
             Arc::clone(&board_mutex),
+            Arc::clone(&options),
+            Arc::clone(&resumed),
             write_interval,
         )));
+        // Watch for system resume (not required, so not a task failure if it ends)
+        tokio::spawn(resume::watch_logind(resume::GDBUS, Arc::clone(&resumed)));
+        // Draw the status bar widgets and system monitors (they stop if their
+        // statistics can not be read)
+        for (widget, screen) in bar_widgets {
+            tokio::spawn(widgets::run_bar_widget(
+                widget,
+                screen,
+                sources.clone(),
+                Arc::clone(&applets_mutex),
+                Arc::clone(&carousels),
+                Arc::clone(&options),
+            ));
+        }
+        if let Some((clock, command)) = clock {
+            tokio::spawn(clock::run_clock(
+                clock,
+                command,
+                Arc::clone(&applets_mutex),
+                Arc::clone(&carousels),
+                Arc::clone(&options),
+            ));
+        }
+        for (monitor, command) in monitors {
+            tokio::spawn(sysmon::run_monitor(
+                monitor,
+                command,
+                sources.clone(),
+                Arc::clone(&applets_mutex),
+                Arc::clone(&carousels),
+                Arc::clone(&options),
+            ));
+        }
+        if let Some(path) = state_file {
+            task_handles.push(tokio::spawn(state::save_periodically(
+                Arc::clone(&applets_mutex),
+                Arc::clone(&carousels),
+                path,
+                state::SAVE_INTERVAL,
+            )));
+        }
 
-        for task in task_handles {
+        if let Some(task) = task_handles.into_iter().next() {
             let task_id = task.id();
             task.await.unwrap();
             println!("Task failure: {task_id}");
-            break;
         }
     });
 }
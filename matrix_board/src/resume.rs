@@ -0,0 +1,98 @@
+// Written by sigroot
+//! sig_rp2040_board - detects system resume so matrices can be rewritten
+//!
+//! The matrix firmware may be reset while the system is suspended. Resume is
+//! detected from systemd-logind's PrepareForSleep signal when available and
+//! otherwise from the wall clock jumping ahead of the monotonic clock (which
+//! does not advance during suspend).
+
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+/// Difference between wall clock and monotonic time treated as a resume
+pub const RESUME_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Detects a suspend by comparing the wall clock with the monotonic clock
+pub struct ResumeDetector {
+    last_instant: Instant,
+    last_wall: SystemTime,
+    threshold: Duration,
+}
+
+impl ResumeDetector {
+    pub fn new(threshold: Duration) -> Self {
+        ResumeDetector {
+            last_instant: Instant::now(),
+            last_wall: SystemTime::now(),
+            threshold,
+        }
+    }
+
+    /// Checks whether the system was suspended since the last check
+    pub fn check(&mut self) -> bool {
+        self.check_at(Instant::now(), SystemTime::now())
+    }
+
+    /// Checks the given clock readings against the previous readings
+    ///
+    /// Separate from 'check' so a clock jump can be simulated
+    pub fn check_at(&mut self, instant: Instant, wall: SystemTime) -> bool {
+        let monotonic = instant.saturating_duration_since(self.last_instant);
+        // A wall clock moved backwards is not a resume
+        let wall_elapsed = wall
+            .duration_since(self.last_wall)
+            .unwrap_or(Duration::ZERO);
+        self.last_instant = instant;
+        self.last_wall = wall;
+        wall_elapsed.saturating_sub(monotonic) > self.threshold
+    }
+}
+
+/// Program used to monitor systemd-logind's signals
+pub const GDBUS: &str = "gdbus";
+
+/// Sets 'resumed' whenever systemd-logind reports the system woke up, using
+/// 'gdbus' (the program, normally GDBUS)
+///
+/// Returns if logind can not be monitored, logging once that only the clock
+/// detects resume from then on
+pub async fn watch_logind(gdbus: &str, resumed: Arc<AtomicBool>) {
+    let child = Command::new(gdbus)
+        .args([
+            "monitor",
+            "--system",
+            "--dest",
+            "org.freedesktop.login1",
+            "--object-path",
+            "/org/freedesktop/login1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not run {gdbus} ({e}), detecting resume from the clock only");
+            return;
+        }
+    };
+    let mut lines = match child.stdout.take() {
+        Some(x) => BufReader::new(x).lines(),
+        None => return,
+    };
+
+    // PrepareForSleep (false,) is sent after resuming
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.contains("PrepareForSleep (false,)") {
+            println!("System resumed");
+            resumed.store(true, Ordering::SeqCst);
+        }
+    }
+    // No system bus or logind
+    eprintln!("{gdbus} stopped, detecting resume from the clock only");
+}
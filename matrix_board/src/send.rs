@@ -0,0 +1,252 @@
+// Written by sigroot
+//! sig_rp2040_board - one-shot client for sending commands from scripts
+//!
+//! Connects to a running board, creates (or replaces an orphaned) applet that
+//! is kept after disconnecting, sends a grid and/or bar, prints each response
+//! and exits
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::net::SocketAddr;
+
+use sig_rp2040_applet::client::{BlockingBoardClient, ClientError};
+use sig_rp2040_applet::image::{Fit, Image, ImageError, Tone};
+use sig_rp2040_applet::{Command, Opcode, OrphanPolicy, Separator};
+
+pub const SEND_HELP_PAGE: &str = "\
+Send a grid and/or bar to a running board and exit.
+
+    $sig_rp2040_board send [-p <port>] [-a <applet>] [-s <screen>]
+                           [-e <separator>] [-k <seconds>|forever]
+                           [-g <grid>] [-b <bar>]
+
+Flags:
+    -p  Set port (default 27072)
+    -a  Set applet number (default 1)
+    -s  Set screen number (default 0)
+    -e  Set separator type 0-8 (default 3 if -b is given, otherwise 0)
+    -k  Keep the applet after disconnecting for a number of seconds or
+        'forever' (default forever)
+    -g  Grid as 90 comma separated values, '-' to read values from stdin, or
+        the path of a text file of values or an image (PBM/PGM/PPM/PNG,
+        stretched to 9x10)
+    -b  Bar as 9 comma separated values (1 value from 0-100 for a progress
+        bar separator) or '-' to read values from stdin
+    -h  Display this menu
+";
+
+/// Values collected from the flags shared by the one-shot clients (-p, -a, -s
+/// and -k)
+pub struct ClientOptions {
+    pub port: u16,
+    pub app_num: u8,
+    pub screen: u8,
+    pub on_disconnect: OrphanPolicy,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            port: 27072,
+            app_num: 1,
+            screen: 0,
+            on_disconnect: OrphanPolicy::KeepForever,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Collects a flag's value if it is a shared flag, false if it is not
+    pub fn parse_flag(&mut self, flag: &str, value: &str) -> Result<bool, String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid argument for {flag}: {e}");
+        match flag {
+            "-p" => self.port = value.parse().map_err(|e| invalid(&e))?,
+            "-a" => self.app_num = value.parse().map_err(|e| invalid(&e))?,
+            "-s" => self.screen = value.parse().map_err(|e| invalid(&e))?,
+            "-k" => {
+                self.on_disconnect = match value {
+                    "forever" => OrphanPolicy::KeepForever,
+                    _ => OrphanPolicy::Keep(value.parse().map_err(|e| invalid(&e))?),
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Values collected from the send subcommand's arguments
+pub struct SendOptions {
+    pub client: ClientOptions,
+    pub separator: Separator,
+    pub grid: Option<Vec<u8>>,
+    pub bar: Option<Vec<u8>>,
+}
+
+/// Collects user parameters (every flag takes one argument), None if the
+/// help page should be shown
+pub fn parse_arguments(args: &[String]) -> Result<Option<SendOptions>, String> {
+    let mut options = SendOptions {
+        client: ClientOptions::default(),
+        separator: Separator::Empty,
+        grid: None,
+        bar: None,
+    };
+    let mut separator = None;
+    let mut bar = None;
+    let mut current_parameter = 0;
+    while current_parameter < args.len() {
+        let flag = args[current_parameter].as_str();
+        let value = match args.get(current_parameter + 1) {
+            Some(x) if flag != "-h" => x.as_str(),
+            _ => return Ok(None),
+        };
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid argument for {flag}: {e}");
+        match flag {
+            _ if options.client.parse_flag(flag, value)? => (),
+            "-e" => {
+                let value = value.parse::<u8>().map_err(|e| invalid(&e))?;
+                separator =
+                    Some(Separator::from_u8(value).ok_or_else(|| invalid(&"expected 0-8"))?);
+            }
+            "-g" => options.grid = Some(read_grid(value).map_err(|e| invalid(&e))?),
+            "-b" => bar = Some(value),
+            _ => return Ok(None),
+        }
+        current_parameter += 2;
+    }
+    options.separator = separator.unwrap_or(match bar {
+        Some(_) => Separator::Variable,
+        None => Separator::Empty,
+    });
+    // Progress bar separators take a single value
+    if let Some(value) = bar {
+        let count = if options.separator == Separator::Progress {
+            1
+        } else {
+            9
+        };
+        options.bar =
+            Some(read_values(value, count).map_err(|e| format!("Invalid argument for -b: {e}"))?);
+    }
+    Ok(Some(options))
+}
+
+/// Runs the send subcommand with its arguments, returning the exit code
+pub fn send(args: &[String]) -> i32 {
+    let SendOptions {
+        client:
+            ClientOptions {
+                port,
+                app_num,
+                screen,
+                on_disconnect,
+            },
+        separator,
+        grid,
+        bar,
+    } = match parse_arguments(args) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            println!("{SEND_HELP_PAGE}");
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+
+    // Create applet, then send grid and bar
+    let mut create = Command::create(app_num, separator);
+    create.on_disconnect = on_disconnect;
+    let mut commands = vec![create];
+    if let Some(x) = grid {
+        commands.push(Command::new(Opcode::UpdateGrid, app_num, x));
+    }
+    if let Some(x) = bar {
+        commands.push(Command::new(Opcode::UpdateBar, app_num, x));
+    }
+    for command in commands.iter_mut() {
+        command.screen = screen;
+    }
+    send_commands(port, &commands)
+}
+
+/// Sends commands to the board on a port in order, printing each response and
+/// returning the first error code (or 0)
+pub fn send_commands(port: u16, commands: &[Command]) -> i32 {
+    let mut client = match BlockingBoardClient::connect(SocketAddr::from(([127, 0, 0, 1], port))) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not connect to board on port {port}: {e}");
+            return 1;
+        }
+    };
+
+    for command in commands {
+        let response = match client.send(command) {
+            Ok(x) => x,
+            Err(ClientError::Response(x)) => x,
+            Err(e) => {
+                eprintln!("Failed to send command: {e}");
+                return 1;
+            }
+        };
+        let code = response as u8;
+        println!("{:?} {code}: {}", command.opcode, response.description());
+        if !response.is_success() {
+            return code as i32;
+        }
+    }
+    0
+}
+
+/// Reads comma (or whitespace) separated values from an argument or stdin
+fn read_values(value: &str, count: usize) -> Result<Vec<u8>, String> {
+    let text = match value {
+        "-" => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| e.to_string())?;
+            text
+        }
+        _ => value.to_string(),
+    };
+    parse_values(&text, count)
+}
+
+/// Reads a grid from an argument, stdin, a text file or an image
+fn read_grid(value: &str) -> Result<Vec<u8>, String> {
+    // Arguments of only values are read as values unless such a file exists
+    let values = value
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == ',' || c.is_whitespace());
+    match fs::metadata(value) {
+        _ if value == "-" => return read_values(value, 90),
+        Ok(_) => (),
+        Err(_) if values => return read_values(value, 90),
+        Err(_) => return Err(format!("file not found: {value}")),
+    }
+    let data = fs::read(value).map_err(|e| e.to_string())?;
+    match Image::decode(&data) {
+        Ok(x) => Ok(x.to_grid(Fit::Stretch, Tone::Gray).concat()),
+        Err(ImageError::UnknownFormat) => parse_values(&String::from_utf8_lossy(&data), 90),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses exactly 'count' comma (or whitespace) separated values
+pub fn parse_values(text: &str, count: usize) -> Result<Vec<u8>, String> {
+    let values = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<u8>().map_err(|e| format!("'{x}': {e}")))
+        .collect::<Result<Vec<u8>, String>>()?;
+    match values.len() == count {
+        true => Ok(values),
+        false => Err(format!("expected {count} values got {}", values.len())),
+    }
+}
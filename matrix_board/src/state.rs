@@ -0,0 +1,133 @@
+// Written by sigroot
+//! sig_rp2040_board - applet snapshots kept across server restarts
+//!
+//! Restored applets keep their session tokens and are displayed as orphans.
+//! For the session grace period their owners may resume them, after which any
+//! stream may create an applet in their place
+
+use crate::applet::BoardApplet;
+use crate::carousel::Carousels;
+use sig_rp2040_applet::Applet;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+/// How often the applets are checked for changes to save
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// One applet and the slot it was displayed in (or waits in the carousel of)
+#[derive(Serialize, Deserialize)]
+struct SlotSnapshot<A> {
+    screen: usize,
+    app_num: usize,
+    applet: A,
+    /// Applet waits for its turn in the slot's carousel
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    waiting: bool,
+}
+
+/// Converts every applet (shown or waiting in a carousel) to the JSON stored
+/// in the state file, except the lowest priority applets the board draws
+/// itself
+pub fn snapshot(
+    applets: &[[Option<BoardApplet>; 4]],
+    carousels: &Carousels,
+) -> serde_json::Result<String> {
+    let mut slots = Vec::new();
+    for (screen, screen_applets) in applets.iter().enumerate() {
+        for (app_num, applet) in screen_applets.iter().enumerate() {
+            if let Some(x) = applet.as_ref().filter(|x| !x.is_lowest_priority()) {
+                slots.push(SlotSnapshot {
+                    screen,
+                    app_num,
+                    applet: &x.applet,
+                    waiting: false,
+                });
+            }
+        }
+    }
+    // Waiting applets follow the shown ones in carousel order
+    for ((screen, app_num), applet) in carousels.iter().filter(|x| !x.1.is_lowest_priority()) {
+        slots.push(SlotSnapshot {
+            screen,
+            app_num,
+            applet: &applet.applet,
+            waiting: true,
+        });
+    }
+    serde_json::to_string(&slots)
+}
+
+/// Writes a snapshot to the state file (replacing it in one step)
+pub fn save(path: &Path, snapshot: &str) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, snapshot)?;
+    fs::rename(&temp_path, path)
+}
+
+/// Reads the state file into an applet table for the given number of screens
+/// and its carousels
+///
+/// Restored applets are orphaned (as of now) and slots outside the table are
+/// ignored. Waiting applets rejoin their slot's carousel if a carousel applet
+/// is shown there (and are shown if the slot is empty)
+pub fn load(path: &Path, screens: usize) -> io::Result<(Vec<[Option<BoardApplet>; 4]>, Carousels)> {
+    let slots: Vec<SlotSnapshot<Applet>> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut applets: Vec<[Option<BoardApplet>; 4]> =
+        (0..screens).map(|_| [None, None, None, None]).collect();
+    let mut carousels = Carousels::default();
+    let now = Instant::now();
+    for slot in slots {
+        if slot.screen >= screens || slot.app_num >= 4 {
+            continue;
+        }
+        let applet = BoardApplet::restored(slot.applet);
+        let location = (slot.screen, slot.app_num);
+        match &applets[slot.screen][slot.app_num] {
+            Some(shown) if slot.waiting && shown.applet.is_carousel() => {
+                let shown = shown.id();
+                carousels.join(location, shown, applet, now);
+            }
+            Some(_) if slot.waiting => (),
+            _ => applets[slot.screen][slot.app_num] = Some(applet),
+        }
+    }
+    Ok((applets, carousels))
+}
+
+/// Periodically saves the applets to the state file when they have changed
+pub async fn save_periodically(
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    path: PathBuf,
+    save_interval: Duration,
+) {
+    let mut clock = interval(save_interval);
+    let mut last_snapshot = String::new();
+    loop {
+        clock.tick().await;
+        let snapshot = match snapshot(
+            &applets_mutex.try_lock().unwrap(),
+            &carousels.try_lock().unwrap(),
+        ) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to snapshot applets: {e}");
+                continue;
+            }
+        };
+        if snapshot != last_snapshot {
+            match save(&path, &snapshot) {
+                Ok(()) => last_snapshot = snapshot,
+                Err(e) => eprintln!("Failed to save state file {}: {e}", path.display()),
+            }
+        }
+    }
+}
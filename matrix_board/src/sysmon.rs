@@ -0,0 +1,194 @@
+// Written by sigroot
+//! sig_rp2040_board - built-in system monitor applets
+//!
+//! Each monitor takes a slot the way a stream's CreateApplet would and redraws
+//! its grid every second
+
+use crate::applet::BoardApplet;
+use crate::carousel::Carousels;
+use crate::system::{self, CpuTimes, Sources};
+use crate::{command_builtin, create_builtin, stop_applet, Options, NEXT_STREAM_ID};
+
+use sig_rp2040_applet::{Allocation, Command, Opcode, Separator};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+
+/// How often monitors are redrawn
+pub const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Statistic a monitor applet shows
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Monitor {
+    /// Load of each core as a column (neighbouring cores share a column if
+    /// there are more than 9)
+    Cpu,
+    /// Memory in use, filling the grid from the bottom
+    Memory,
+    /// Bytes received and sent per second as a sparkline
+    Network,
+    /// Sectors read and written per second as a sparkline
+    Disk,
+}
+
+/// Converts a monitor name with an optional slot ("cpu", "cpu:2" or
+/// "cpu:top") to the monitor and the CreateApplet command that places it
+pub fn parse_monitor(value: &str) -> Option<(Monitor, Command)> {
+    let (name, slot) = match value.split_once(':') {
+        Some((name, slot)) => (name, Some(slot)),
+        None => (value, None),
+    };
+    let monitor = match name {
+        "cpu" => Monitor::Cpu,
+        "memory" => Monitor::Memory,
+        "network" => Monitor::Network,
+        "disk" => Monitor::Disk,
+        _ => return None,
+    };
+    let mut command = Command::create(0, Separator::Solid);
+    match slot.map(|x| (x, x.parse::<u8>())) {
+        // Without a slot the first free grid slot is used
+        None => command.allocate = Allocation::FirstFree,
+        Some((_, Ok(app_num))) => command.app_num = app_num,
+        Some((name, Err(_))) => command.slot = Some(name.to_string()),
+    }
+    Some((monitor, command))
+}
+
+/// Samples a monitor needs to compare against
+#[derive(Default)]
+pub struct MonitorState {
+    cpus: Vec<CpuTimes>,
+    /// Previous value of a network or disk counter
+    counter: Option<u64>,
+    /// Recent per second changes of the counter (oldest first)
+    history: VecDeque<u64>,
+}
+
+/// Fills a column from the bottom to a 0-100 value (the top LED is dimmed to
+/// show part of a step)
+fn fill_column(grid: &mut [[u8; 9]; 10], column: usize, value: u8) {
+    let level = value.min(100) as f32 * 10.0 / 100.0;
+    for row in 0..10 {
+        grid[9 - row][column] = ((level - row as f32).clamp(0.0, 1.0) * 255.0) as u8;
+    }
+}
+
+/// Draws columns for the most recent changes of a counter, scaled to the
+/// largest change shown
+fn sparkline(state: &mut MonitorState, counter: u64) -> [[u8; 9]; 10] {
+    if let Some(previous) = state.counter {
+        state.history.push_back(counter.saturating_sub(previous));
+        if state.history.len() > 9 {
+            state.history.pop_front();
+        }
+    }
+    state.counter = Some(counter);
+
+    let mut grid = [[0; 9]; 10];
+    let largest = state.history.iter().copied().max().unwrap_or(0).max(1);
+    let offset = 9 - state.history.len();
+    for (i, change) in state.history.iter().enumerate() {
+        fill_column(&mut grid, offset + i, (change * 100 / largest) as u8);
+    }
+    grid
+}
+
+/// Reads a monitor's statistics and draws its grid
+pub fn draw_monitor(
+    monitor: Monitor,
+    sources: &Sources,
+    state: &mut MonitorState,
+) -> io::Result<[[u8; 9]; 10]> {
+    match monitor {
+        Monitor::Cpu => {
+            // Skip the combined times of all CPUs (unless no core is listed)
+            let cpus = system::cpu_times(&sources.proc)?;
+            let first = if cpus.len() > 1 { 1 } else { 0 };
+            let loads: Vec<u8> = match state.cpus.len() == cpus.len() {
+                true => cpus[first..]
+                    .iter()
+                    .zip(&state.cpus[first..])
+                    .map(|(x, y)| x.load_since(y))
+                    .collect(),
+                false => vec![0; cpus.len() - first],
+            };
+            state.cpus = cpus;
+
+            // Spread the cores over the 9 columns
+            let mut grid = [[0; 9]; 10];
+            for column in 0..9 {
+                let start = column * loads.len() / 9;
+                let end = ((column + 1) * loads.len() / 9).max(start + 1);
+                let shared = &loads[start..end];
+                let load = shared.iter().map(|x| *x as usize).sum::<usize>() / shared.len();
+                fill_column(&mut grid, column, load as u8);
+            }
+            Ok(grid)
+        }
+        Monitor::Memory => {
+            let used = system::memory_used(&sources.proc)?;
+            let mut grid = [[0; 9]; 10];
+            for column in 0..9 {
+                fill_column(&mut grid, column, used);
+            }
+            Ok(grid)
+        }
+        Monitor::Network => Ok(sparkline(state, system::network_bytes(&sources.proc)?)),
+        Monitor::Disk => Ok(sparkline(
+            state,
+            system::disk_sectors(&sources.proc, &sources.sys)?,
+        )),
+    }
+}
+
+/// Places a monitor applet as its CreateApplet command describes and redraws
+/// it until it is removed or its statistics can not be read
+pub async fn run_monitor(
+    monitor: Monitor,
+    command: Command,
+    sources: Sources,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    options: Arc<Mutex<Options>>,
+) {
+    let owner = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    let location = match create_builtin(command, owner, &applets_mutex, &carousels, &options) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{e} for {monitor:?} monitor");
+            return;
+        }
+    };
+
+    let mut state = MonitorState::default();
+    let mut clock = interval(MONITOR_INTERVAL);
+    loop {
+        clock.tick().await;
+        let grid = match draw_monitor(monitor, &sources, &mut state) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Stopping {monitor:?} monitor: {e}");
+                break;
+            }
+        };
+        let update = Command::new(Opcode::UpdateGrid, 0, grid.concat());
+        if !command_builtin(&applets_mutex, &carousels, location, owner, &[update]) {
+            eprintln!("{monitor:?} monitor was removed");
+            return;
+        }
+    }
+
+    // Remove the applet like a disconnecting stream
+    let session_grace = options.try_lock().unwrap().session_grace;
+    stop_applet(
+        &mut applets_mutex.try_lock().unwrap(),
+        &mut carousels.try_lock().unwrap(),
+        &[location],
+        owner,
+        session_grace,
+    );
+}
@@ -0,0 +1,174 @@
+// Written by sigroot
+//! sig_rp2040_board - system statistics read from /proc and /sys
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where system statistics are read from (changed to point tests at fixtures)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sources {
+    pub proc: PathBuf,
+    pub sys: PathBuf,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            proc: PathBuf::from("/proc"),
+            sys: PathBuf::from("/sys"),
+        }
+    }
+}
+
+/// Time a CPU has spent busy and in total (in clock ticks)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub busy: u64,
+    pub total: u64,
+}
+
+impl CpuTimes {
+    /// Percentage of time the CPU was busy since an earlier sample
+    pub fn load_since(&self, earlier: &CpuTimes) -> u8 {
+        let total = self.total.saturating_sub(earlier.total);
+        let busy = self.busy.saturating_sub(earlier.busy);
+        match total {
+            0 => 0,
+            _ => (busy * 100 / total).min(100) as u8,
+        }
+    }
+}
+
+/// Creates an error for a file that could not be understood
+fn invalid(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected contents in {}", path.display()),
+    )
+}
+
+/// Reads the times of every CPU from 'stat' (all CPUs combined first, then
+/// each core)
+pub fn cpu_times(proc: &Path) -> io::Result<Vec<CpuTimes>> {
+    let path = proc.join("stat");
+    let mut cpus = Vec::new();
+    for line in fs::read_to_string(&path)?.lines() {
+        if !line.starts_with("cpu") {
+            continue;
+        }
+        // user nice system idle iowait irq softirq steal (guest time is already
+        // counted in user)
+        let values: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .take(8)
+            .map(|x| x.parse::<u64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid(&path))?;
+        if values.len() < 4 {
+            return Err(invalid(&path));
+        }
+        let total: u64 = values.iter().sum();
+        let idle = values[3] + values.get(4).copied().unwrap_or(0);
+        cpus.push(CpuTimes {
+            busy: total - idle,
+            total,
+        });
+    }
+    match cpus.is_empty() {
+        true => Err(invalid(&path)),
+        false => Ok(cpus),
+    }
+}
+
+/// Reads the percentage of memory in use from 'meminfo'
+pub fn memory_used(proc: &Path) -> io::Result<u8> {
+    let path = proc.join("meminfo");
+    let text = fs::read_to_string(&path)?;
+    let field = |name: &str| {
+        text.lines()
+            .find(|x| x.starts_with(name))
+            .and_then(|x| x.split_whitespace().nth(1))
+            .and_then(|x| x.parse::<u64>().ok())
+    };
+    match (field("MemTotal:"), field("MemAvailable:")) {
+        (Some(total), Some(available)) if total > 0 => {
+            Ok((total.saturating_sub(available) * 100 / total) as u8)
+        }
+        _ => Err(invalid(&path)),
+    }
+}
+
+/// Reads the charge percentage of the first battery in 'class/power_supply'
+pub fn battery_level(sys: &Path) -> io::Result<u8> {
+    let supplies = sys.join("class/power_supply");
+    let mut batteries: Vec<PathBuf> = fs::read_dir(&supplies)?
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_name().to_string_lossy().starts_with("BAT"))
+        .map(|x| x.path())
+        .collect();
+    batteries.sort();
+    let path = batteries
+        .first()
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No battery in {}", supplies.display()),
+        ))?
+        .join("capacity");
+    fs::read_to_string(&path)?
+        .trim()
+        .parse::<u8>()
+        .map(|x| x.min(100))
+        .map_err(|_| invalid(&path))
+}
+
+/// Reads the bytes received and sent by every network interface except
+/// loopback from 'net/dev'
+pub fn network_bytes(proc: &Path) -> io::Result<u64> {
+    let path = proc.join("net/dev");
+    let mut bytes = 0;
+    // Two header lines, then "<interface>: <rx bytes> 7 more rx fields <tx bytes> ..."
+    for line in fs::read_to_string(&path)?.lines().skip(2) {
+        let (interface, values) = line.split_once(':').ok_or_else(|| invalid(&path))?;
+        if interface.trim() == "lo" {
+            continue;
+        }
+        let values: Vec<&str> = values.split_whitespace().collect();
+        for index in [0, 8] {
+            bytes += values
+                .get(index)
+                .and_then(|x| x.parse::<u64>().ok())
+                .ok_or_else(|| invalid(&path))?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Reads the sectors read and written by every disk from 'diskstats'
+/// (partitions and virtual devices are skipped by only counting devices that
+/// are listed in 'block' of sys, and device mapper volumes because their
+/// sectors are counted again on the disks below them)
+pub fn disk_sectors(proc: &Path, sys: &Path) -> io::Result<u64> {
+    let path = proc.join("diskstats");
+    let mut sectors = 0;
+    // "<major> <minor> <name> <reads> <merged> <sectors read> <ms> <writes>
+    // <merged> <sectors written> ..."
+    for line in fs::read_to_string(&path)?.lines() {
+        let values: Vec<&str> = line.split_whitespace().collect();
+        let name = values.get(2).ok_or_else(|| invalid(&path))?;
+        if ["loop", "ram", "dm-"].iter().any(|x| name.starts_with(x)) {
+            continue;
+        }
+        if !sys.join("block").join(name).exists() {
+            continue;
+        }
+        for index in [5, 9] {
+            sectors += values
+                .get(index)
+                .and_then(|x| x.parse::<u64>().ok())
+                .ok_or_else(|| invalid(&path))?;
+        }
+    }
+    Ok(sectors)
+}
@@ -0,0 +1,101 @@
+// Written by sigroot
+//! sig_rp2040_board - transition effects between the applets shown in a slot
+
+use crate::applet::BoardApplet;
+use sig_rp2040_applet::Transition;
+use std::time::Instant;
+
+/// Transition running in one slot
+struct Running {
+    effect: Transition,
+    from: [[u8; 9]; 11],
+    frame: u16,
+    frames: u16,
+}
+
+/// What one slot showed in the previous frame
+#[derive(Default)]
+struct Slot {
+    /// Id of the applet shown (None if empty)
+    shown: Option<u64>,
+    /// Transition chosen by the applet shown
+    transition: (Option<Transition>, u16),
+    region: [[u8; 9]; 11],
+    running: Option<Running>,
+}
+
+/// Renders a transition whenever the applet shown in a slot changes (an
+/// applet is created, removed, covered or swapped by a carousel)
+pub struct Transitions {
+    slots: Vec<[Slot; 4]>,
+    /// Effect used unless the applets involved choose one
+    effect: Transition,
+    /// Frames a transition takes unless the applets involved choose
+    frames: u16,
+}
+
+impl Transitions {
+    pub fn new(effect: Transition, frames: u16) -> Self {
+        Transitions {
+            slots: Vec::new(),
+            effect,
+            frames,
+        }
+    }
+
+    /// Regions (separator and grid) of a matrix's slots for this frame at an
+    /// animation tick and time, moving running transitions on by one frame
+    pub fn render(
+        &mut self,
+        matrix: usize,
+        applets: &[Option<BoardApplet>; 4],
+        tick: u64,
+        now: Instant,
+    ) -> [[[u8; 9]; 11]; 4] {
+        while self.slots.len() <= matrix {
+            self.slots.push(Default::default());
+        }
+        let mut regions = [[[0; 9]; 11]; 4];
+        for (i, applet) in applets.iter().enumerate() {
+            let slot = &mut self.slots[matrix][i];
+            let region = applet
+                .as_ref()
+                .map_or([[0; 9]; 11], |x| x.get_board(tick, now));
+            let shown = applet.as_ref().map(|x| x.id());
+
+            // Start a transition from whatever was shown last, using the effect
+            // of the applet shown next (or of the applet removed)
+            if shown != slot.shown {
+                let next = applet.as_ref().map_or((None, 0), |x| x.applet.transition());
+                let effect = next.0.or(slot.transition.0).unwrap_or(self.effect);
+                let frames = [next.1, slot.transition.1, self.frames]
+                    .into_iter()
+                    .find(|x| *x > 0)
+                    .unwrap_or(0);
+                slot.running = (effect != Transition::None && frames > 0).then_some(Running {
+                    effect,
+                    from: slot.region,
+                    frame: 0,
+                    frames,
+                });
+                slot.shown = shown;
+                slot.transition = next;
+            }
+
+            // Mix the previous and next region (the status bar is only 1 row)
+            let rows = if i == 0 { 1 } else { 11 };
+            regions[i] = region;
+            if let Some(x) = &mut slot.running {
+                x.frame += 1;
+                let progress = x.frame as f32 / x.frames as f32;
+                let mixed = x.effect.apply(&x.from[..rows], &region[..rows], progress);
+                regions[i][..rows].copy_from_slice(&mixed);
+                if x.frame >= x.frames {
+                    slot.running = None;
+                }
+            }
+            slot.region = regions[i];
+        }
+        regions
+    }
+}
@@ -0,0 +1,170 @@
+// Written by sigroot
+//! sig_rp2040_board - status bar widgets drawn by the board itself
+
+use crate::applet::BoardApplet;
+use crate::carousel::Carousels;
+use crate::system::{self, CpuTimes, Sources};
+use crate::{find_owned, remove_shown, replaceable, stop_applet, Options, NEXT_STREAM_ID};
+
+use sig_rp2040_applet::{Applet, Command, Opcode, OrphanPolicy, Separator};
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::interval;
+
+/// How often widgets are redrawn
+pub const WIDGET_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Status bar widget shown as a progress bar
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Widget {
+    /// Charge of the first battery
+    Battery,
+    /// Load of all CPUs combined
+    Cpu,
+    /// Memory in use
+    Memory,
+    /// Fills once a minute with the clock's seconds
+    Seconds,
+}
+
+/// Converts a widget name and the screen whose status bar it is drawn on
+/// ("<widget>[:<screen>]", default screen 0)
+pub fn parse_widget(value: &str) -> Option<(Widget, u8)> {
+    let (name, screen) = match value.split_once(':') {
+        Some((name, screen)) => (name, screen.parse().ok()?),
+        None => (value, 0),
+    };
+    let widget = match name {
+        "battery" => Widget::Battery,
+        "cpu" => Widget::Cpu,
+        "memory" => Widget::Memory,
+        "seconds" => Widget::Seconds,
+        _ => return None,
+    };
+    Some((widget, screen))
+}
+
+/// Reads a widget's current value (0-100), keeping the previous CPU sample to
+/// measure load against
+pub fn widget_level(
+    widget: Widget,
+    sources: &Sources,
+    previous: &mut Option<CpuTimes>,
+) -> io::Result<u8> {
+    match widget {
+        Widget::Battery => system::battery_level(&sources.sys),
+        Widget::Memory => system::memory_used(&sources.proc),
+        Widget::Cpu => {
+            let current = system::cpu_times(&sources.proc)?[0];
+            let load = previous.map_or(0, |x| current.load_since(&x));
+            *previous = Some(current);
+            Ok(load)
+        }
+        Widget::Seconds => {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                % 60;
+            Ok((seconds * 100 / 59) as u8)
+        }
+    }
+}
+
+/// Puts a widget's applet on a status bar unless another applet uses it,
+/// returning whether the widget's applet is in the slot (shown, taking turns
+/// with other widgets or covered)
+///
+/// Widget applets have the lowest priority, so any stream's applet covers them.
+/// Orphans are replaced once they can no longer be resumed, unless they are
+/// kept forever
+pub fn claim_bar(
+    slot: &mut Option<BoardApplet>,
+    location: (usize, usize),
+    carousels: &mut Carousels,
+    owner: u64,
+    session_grace: Duration,
+) -> bool {
+    if find_owned(slot, location, carousels, owner).is_some() {
+        return true;
+    }
+    while slot
+        .as_ref()
+        .is_some_and(|x| x.applet.on_disconnect() != OrphanPolicy::KeepForever)
+        && replaceable(slot, session_grace)
+    {
+        remove_shown(slot, location, carousels);
+    }
+    let mut applet = Applet::new(Separator::Variable);
+    // Widgets sharing a status bar take turns
+    applet.set_carousel(true, 0);
+    let mut applet = BoardApplet::new(applet, owner);
+    applet.set_lowest_priority(true);
+    match slot {
+        None => *slot = Some(applet),
+        Some(x) if x.is_lowest_priority() && x.applet.is_carousel() => {
+            carousels.join(location, x.id(), applet, Instant::now());
+        }
+        Some(_) => return false,
+    }
+    true
+}
+
+/// Draws a widget on the status bar of a screen's first matrix whenever no
+/// stream uses it (taking turns with the other widgets there)
+pub async fn run_bar_widget(
+    widget: Widget,
+    screen: u8,
+    sources: Sources,
+    applets_mutex: Arc<Mutex<Vec<[Option<BoardApplet>; 4]>>>,
+    carousels: Arc<Mutex<Carousels>>,
+    options: Arc<Mutex<Options>>,
+) {
+    let owner = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    let (location, session_grace) = {
+        let options = options.try_lock().unwrap();
+        match options.screens.get(screen as usize) {
+            Some(x) => ((x[0], 0), options.session_grace),
+            None => {
+                eprintln!("Invalid screen for {widget:?} widget");
+                return;
+            }
+        }
+    };
+    let mut previous = None;
+    let mut clock = interval(WIDGET_INTERVAL);
+    loop {
+        clock.tick().await;
+        let level = match widget_level(widget, &sources, &mut previous) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Stopping {widget:?} widget: {e}");
+                break;
+            }
+        };
+        let bar = Command::new(Opcode::UpdateBar, 0, Separator::progress(level).to_vec());
+
+        let mut applets = applets_mutex.try_lock().unwrap();
+        let mut carousels = carousels.try_lock().unwrap();
+        let slot = &mut applets[location.0][location.1];
+        if !claim_bar(slot, location, &mut carousels, owner, session_grace) {
+            continue;
+        }
+        if let Some((x, _)) = find_owned(slot, location, &mut carousels, owner) {
+            if let Err(e) = x.applet.command_applet(&bar) {
+                eprintln!("Failed to draw {widget:?} widget: {e}");
+            }
+        }
+    }
+
+    // Give the status bar back (to another widget or an empty slot)
+    stop_applet(
+        &mut applets_mutex.try_lock().unwrap(),
+        &mut carousels.try_lock().unwrap(),
+        &[location],
+        owner,
+        session_grace,
+    );
+}
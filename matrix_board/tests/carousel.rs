@@ -0,0 +1,231 @@
+// Written by sigroot
+//! Tests carousel applets taking turns in a slot
+
+mod common;
+
+use common::{board, options, stream};
+use sig_rp2040_applet::{Command, OrphanPolicy, Separator};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::state::{load, save, snapshot};
+use sig_rp2040_matrix_board::{expire_orphans, run_command, stop_applet, Reply};
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// CreateApplet for slot 1 of a carousel applet
+fn create(on_disconnect: OrphanPolicy, session: bool) -> Command {
+    let mut command = Command::create(1, Separator::Empty);
+    command.carousel = true;
+    command.on_disconnect = on_disconnect;
+    command.session = session;
+    command
+}
+
+/// Owner of the applet shown in slot 1
+fn shown(applets: &[[Option<BoardApplet>; 4]]) -> u64 {
+    applets[0][1].as_ref().unwrap().owner()
+}
+
+#[test]
+fn take_turns() {
+    let options = options();
+    let (mut applets, mut carousels) = board();
+    let mut first = stream();
+    let mut second = stream();
+    for stream in [&mut first, &mut second] {
+        let command = create(OrphanPolicy::Clear, false);
+        let (reply, _) = run_command(
+            command,
+            stream,
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+        assert_eq!(reply.code(), 0);
+    }
+
+    // The second applet waits outside the applet table
+    assert_eq!(shown(&applets), first.id);
+    assert_eq!(carousels.waiting((0, 1)), 1);
+    assert!(carousels.find_owned((0, 1), second.id).is_some());
+
+    // Turns change once the dwell time is over
+    let start = Instant::now();
+    carousels.rotate(&mut applets, options.dwell, start + Duration::from_secs(4));
+    assert_eq!(shown(&applets), first.id);
+    let turn = start + Duration::from_secs(5);
+    carousels.rotate(&mut applets, options.dwell, turn);
+    assert_eq!(shown(&applets), second.id);
+    assert!(carousels.find_owned((0, 1), first.id).is_some());
+    carousels.rotate(&mut applets, options.dwell, turn + Duration::from_secs(5));
+    assert_eq!(shown(&applets), first.id);
+}
+
+#[test]
+fn shown_applet_disconnects() {
+    let options = options();
+    let (mut applets, mut carousels) = board();
+    let mut first = stream();
+    let mut second = stream();
+    let command = create(OrphanPolicy::Keep(10), false);
+    run_command(
+        command,
+        &mut first,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = create(OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut second,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+
+    // A kept applet keeps its place and turns until it expires
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &first.owned,
+        first.id,
+        options.session_grace,
+    );
+    assert_eq!(shown(&applets), first.id);
+    assert!(applets[0][1].as_ref().unwrap().is_orphaned());
+    expire_orphans(
+        &mut applets,
+        &mut carousels,
+        Instant::now() + Duration::from_secs(11),
+    );
+    assert_eq!(shown(&applets), second.id);
+    assert_eq!(carousels.waiting((0, 1)), 0);
+
+    // A cleared applet gives its turn away
+    let mut third = stream();
+    let command = create(OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut third,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &second.owned,
+        second.id,
+        options.session_grace,
+    );
+    assert_eq!(shown(&applets), third.id);
+    assert_eq!(carousels.waiting((0, 1)), 0);
+}
+
+#[test]
+fn waiting_applet_disconnects() {
+    let options = options();
+    let (mut applets, mut carousels) = board();
+    let mut first = stream();
+    let mut second = stream();
+    let command = create(OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut first,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = create(OrphanPolicy::Clear, true);
+    let (reply, _) = run_command(
+        command,
+        &mut second,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let token = match reply {
+        Reply::Response(x) => x.session.unwrap(),
+        x => panic!("expected a session token, got {x:?}"),
+    };
+
+    // The waiting applet is kept for its session to be resumed
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &second.owned,
+        second.id,
+        options.session_grace,
+    );
+    let waiting = carousels.find_session((0, 1), token).unwrap();
+    assert!(waiting.is_orphaned());
+    let now = Instant::now();
+    expire_orphans(&mut applets, &mut carousels, now + Duration::from_secs(29));
+    assert_eq!(carousels.waiting((0, 1)), 1);
+    expire_orphans(&mut applets, &mut carousels, now + Duration::from_secs(31));
+    assert_eq!(carousels.waiting((0, 1)), 0);
+    assert_eq!(shown(&applets), first.id);
+
+    // Without a session it leaves the carousel at once
+    let mut third = stream();
+    let command = create(OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut third,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &third.owned,
+        third.id,
+        options.session_grace,
+    );
+    assert_eq!(carousels.waiting((0, 1)), 0);
+    assert_eq!(shown(&applets), first.id);
+}
+
+#[test]
+fn snapshot_keeps_carousel() {
+    let options = options();
+    let (mut applets, mut carousels) = board();
+    for _ in 0..3 {
+        let command = create(OrphanPolicy::KeepForever, false);
+        run_command(
+            command,
+            &mut stream(),
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+    }
+
+    let path = env::temp_dir().join(format!("sig_rp2040_carousel_{}.json", std::process::id()));
+    save(&path, &snapshot(&applets, &carousels).unwrap()).unwrap();
+    let restored = load(&path, 1);
+    fs::remove_file(&path).unwrap();
+    let (mut applets, mut carousels) = restored.unwrap();
+
+    // Waiting applets rejoin the carousel of the applet shown in their slot
+    assert!(applets[0][1].as_ref().unwrap().applet.is_carousel());
+    assert_eq!(carousels.waiting((0, 1)), 2);
+    let shown = applets[0][1].as_ref().unwrap().id();
+    carousels.rotate(
+        &mut applets,
+        options.dwell,
+        Instant::now() + Duration::from_secs(5),
+    );
+    assert_ne!(applets[0][1].as_ref().unwrap().id(), shown);
+    assert_eq!(carousels.waiting((0, 1)), 2);
+}
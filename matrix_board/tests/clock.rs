@@ -0,0 +1,122 @@
+// Written by sigroot
+//! Tests drawing the built-in clock's faces
+
+use sig_rp2040_matrix_board::clock::{draw_clock, local_offset, parse_clock, Face};
+use std::time::Duration;
+
+/// Converts a 9x10 grid to rows of '#' (lit) and '.' (off)
+fn picture(grid: &[[u8; 9]; 10]) -> Vec<String> {
+    grid.iter()
+        .map(|x| x.iter().map(|y| if *y > 0 { '#' } else { '.' }).collect())
+        .collect()
+}
+
+#[test]
+fn parse() {
+    let (clock, command) = parse_clock("digits:top,12h,blink,seconds,utc-3:30").unwrap();
+    assert_eq!(clock.face, Face::Digits);
+    assert!(clock.twelve_hour && clock.blink && clock.seconds);
+    assert_eq!(clock.offset, Some(-(3 * 3600 + 30 * 60)));
+    assert_eq!(command.slot.as_deref(), Some("top"));
+    let (clock, command) = parse_clock("ring").unwrap();
+    assert_eq!(clock.offset, None);
+    assert!(command.allocates());
+    assert!(parse_clock("digits,utc+99").is_none());
+    // A second sign is not an offset
+    assert!(parse_clock("digits,utc+-3").is_none());
+    assert!(parse_clock("digits,utc+3:-30").is_none());
+    assert!(parse_clock("sundial").is_none());
+}
+
+#[test]
+fn digits_face() {
+    let (clock, _) = parse_clock("digits,12h").unwrap();
+    // 21:47 is 9:47 without a leading zero
+    let time = Duration::from_secs(21 * 3600 + 47 * 60);
+    assert_eq!(
+        picture(&draw_clock(&clock, time)),
+        vec![
+            ".....###.",
+            ".....#.#.",
+            ".....###.",
+            ".......#.",
+            ".....###.",
+            ".#.#.###.",
+            ".#.#...#.",
+            ".###...#.",
+            "...#...#.",
+            "...#...#.",
+        ]
+    );
+}
+
+#[test]
+fn binary_face() {
+    let (clock, _) = parse_clock("binary").unwrap();
+    // 12:34:56
+    let time = Duration::from_secs(12 * 3600 + 34 * 60 + 56);
+    let grid = draw_clock(&clock, time);
+    // Lowest bit (bottom two rows) of 1, 2, 3, 4, 5 and 6
+    assert_eq!(picture(&grid)[9], "#..#..#..");
+    // Third bit of 4, 5 and 6
+    assert_eq!(picture(&grid)[5], "....#.##.");
+}
+
+/// Lit LEDs of a grid as (row, column, brightness)
+fn lit(grid: &[[u8; 9]; 10]) -> Vec<(usize, usize, u8)> {
+    let mut lit = Vec::new();
+    for (row, values) in grid.iter().enumerate() {
+        for (column, value) in values.iter().enumerate() {
+            if *value > 0 {
+                lit.push((row, column, *value));
+            }
+        }
+    }
+    lit
+}
+
+#[test]
+fn ring_face() {
+    let (clock, _) = parse_clock("ring").unwrap();
+    // 15:00 puts the hour dot on the right quarter mark and the minute dot on
+    // the top one
+    let grid = draw_clock(&clock, Duration::from_secs(15 * 3600));
+    assert_eq!(
+        lit(&grid),
+        vec![(0, 4, 255), (4, 0, 32), (5, 8, 128), (9, 4, 32)]
+    );
+    // 9:30 puts the hour dot between the left and top quarter marks and the
+    // minute dot on the bottom one
+    let grid = draw_clock(&clock, Duration::from_secs(9 * 3600 + 30 * 60));
+    assert_eq!(
+        lit(&grid),
+        vec![(0, 4, 32), (3, 0, 128), (4, 0, 32), (5, 8, 32), (9, 4, 255)]
+    );
+}
+
+#[test]
+fn blink() {
+    let time = Duration::from_secs(12 * 3600 + 34 * 60);
+    let on = time + Duration::from_millis(499);
+    let off = time + Duration::from_millis(500);
+    let (digits, _) = parse_clock("digits,blink").unwrap();
+    // The middle is lit for the first half of the second only
+    let grid = draw_clock(&digits, on);
+    assert_eq!((grid[4][4], grid[5][4]), (255, 255));
+    let grid = draw_clock(&digits, off);
+    assert_eq!((grid[4][4], grid[5][4]), (0, 0));
+
+    // Without blink the middle stays off, and the binary face never blinks
+    let (steady, _) = parse_clock("digits").unwrap();
+    assert_eq!(draw_clock(&steady, on), draw_clock(&digits, off));
+    let (binary, _) = parse_clock("binary,blink").unwrap();
+    assert_eq!(draw_clock(&binary, on), draw_clock(&binary, off));
+}
+
+#[test]
+fn offset() {
+    // Every current time zone is within 14 hours of UTC, in quarter hours
+    let offset = local_offset();
+    assert!(offset.abs() <= 14 * 3600);
+    assert_eq!(offset % 900, 0);
+}
@@ -0,0 +1,734 @@
+// Written by sigroot
+//! Tests running commands of streams against the applet table
+
+mod common;
+
+use common::{applets, options, stream};
+use sig_rp2040_applet::{
+    Allocation, Command, Opcode, Orientation, OrphanPolicy, Response, ResponseFormat, Separator,
+    Transition,
+};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::carousel::Carousels;
+use sig_rp2040_matrix_board::transition::Transitions;
+use sig_rp2040_matrix_board::{
+    expire_orphans, parse_slot_names, render_screens, reply_bytes, run_command, stop_applet,
+    take_notices, FrameTime, Preemption, Reply, BUFFER_SIZE,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn wrong_parameter_count() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    for parameters in [vec![], vec![0, 0, 0]] {
+        let command = Command::new(Opcode::CreateApplet, 1, parameters);
+        let (reply, close) = run_command(
+            command,
+            &mut stream,
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+        assert_eq!(reply.code(), 40);
+        assert!(!close);
+    }
+    assert!(applets[0][1].is_none());
+
+    // The stream can still create the applet
+    let command = Command::create(1, Separator::Empty);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert!(matches!(reply, Reply::Response(ref x) if x.code == 0));
+    assert!(!close);
+    assert_eq!(stream.owned, vec![(0, 1)]);
+    assert!(applets[0][1].is_some());
+}
+
+#[test]
+fn failing_no_ack() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    let command = Command::create(1, Separator::Empty);
+    run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+
+    // A failed no-ack command leaves nothing for the client to read
+    let mut command = Command::new(Opcode::UpdateGrid, 1, vec![1; 89]);
+    command.no_ack = true;
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply, Reply::Silent);
+    assert!(reply_bytes(&reply, ResponseFormat::Byte).is_empty());
+    assert!(!close);
+
+    // So the next command's response is the next thing it reads
+    let mut command = Command::new(Opcode::UpdateGrid, 1, vec![1; 90]);
+    command.id = Some(7);
+    command.response = Some(ResponseFormat::Json);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert!(!close);
+    let data = reply_bytes(&reply, stream.format);
+    let response: Response = serde_json::from_slice(&data).unwrap();
+    assert_eq!((response.code, response.request_id), (0, Some(7)));
+    assert_eq!(reply_bytes(&reply, ResponseFormat::Byte), vec![0]);
+}
+
+#[test]
+fn hello() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    let (reply, close) = run_command(
+        Command::hello(),
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let hello = match reply {
+        Reply::Hello(x) => x,
+        x => panic!("expected a Hello response, got {x:?}"),
+    };
+    assert!(!close);
+    assert_eq!(hello.encoding, ResponseFormat::Byte);
+    assert_eq!(hello.max_parameters, 90);
+    assert_eq!(hello.buffer_size, BUFFER_SIZE as u32);
+
+    // The client chooses the encoding of following responses
+    let command = Command::hello_with(ResponseFormat::Json);
+    let (reply, _) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert!(matches!(reply, Reply::Hello(ref x) if x.encoding == ResponseFormat::Json));
+    assert_eq!(stream.format, ResponseFormat::Json);
+    // Hello is a JSON line in either encoding
+    assert_eq!(
+        reply_bytes(&reply, ResponseFormat::Byte).last(),
+        Some(&b'\n')
+    );
+
+    // Unsupported versions close the stream
+    let command = Command::new(Opcode::Hello, 0, vec![200]);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 42);
+    assert!(close);
+}
+
+#[test]
+fn too_many_parameters() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    run_command(
+        Command::create(1, Separator::Empty),
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = Command::new(Opcode::UpdateGrid, 1, vec![0; 91]);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 33);
+    assert!(!close);
+}
+
+#[test]
+fn resume_owned_applet() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    let mut command = Command::create(1, Separator::Empty);
+    command.session = true;
+    let (reply, _) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let token = match reply {
+        Reply::Response(x) => x.session.unwrap(),
+        x => panic!("expected a session token, got {x:?}"),
+    };
+
+    // Resuming the stream's own applet does not list it twice
+    for _ in 0..2 {
+        applets[0][1].as_mut().unwrap().set_orphaned(true);
+        let command = Command::new(Opcode::Resume, 1, token.to_be_bytes().to_vec());
+        let (reply, close) = run_command(
+            command,
+            &mut stream,
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+        assert_eq!(reply.code(), 0);
+        assert!(!close);
+    }
+    assert_eq!(stream.owned, vec![(0, 1)]);
+}
+
+#[test]
+fn slot_names() {
+    let names = parse_slot_names("status=0,main=1,info=3").unwrap();
+    assert_eq!(names[2], ("info".to_string(), 3));
+    assert!(parse_slot_names("main=4").is_none());
+    assert!(parse_slot_names("main=1,main=2").is_none());
+    assert!(parse_slot_names("2=1").is_none());
+    assert!(parse_slot_names("main").is_none());
+
+    let mut options = options();
+    options.slot_names = names;
+    options.orientation = Orientation::Rotate180;
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    // Unknown names are errors but the stream stays open
+    let mut command = Command::create(0, Separator::Empty);
+    command.slot = Some("top".to_string());
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 30);
+    assert!(!close);
+    // Named grid slots count from the bottom when the matrix is upside down
+    let mut command = Command::create(0, Separator::Empty);
+    command.slot = Some("main".to_string());
+    let (reply, _) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert!(matches!(reply, Reply::Response(ref x) if x.app_num == Some(3)));
+}
+
+#[test]
+fn no_free_slot() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    let mut allocate = Command::create(0, Separator::Empty);
+    allocate.allocate = Allocation::FirstFree;
+    for app_num in 1..4 {
+        let (reply, _) = run_command(
+            allocate.clone(),
+            &mut stream,
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+        assert!(matches!(reply, Reply::Response(ref x) if x.app_num == Some(app_num)));
+    }
+    // The stream keeps its applets and may try again later
+    let (reply, close) = run_command(
+        allocate,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 34);
+    assert!(!close);
+    assert_eq!(stream.owned.len(), 3);
+}
+
+#[test]
+fn named_slot_taken() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut first = stream();
+    let mut second = stream();
+    let mut command = Command::create(0, Separator::Empty);
+    command.slot = Some("middle".to_string());
+    run_command(
+        command.clone(),
+        &mut first,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let (reply, close) = run_command(
+        command,
+        &mut second,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 34);
+    assert!(!close);
+}
+
+/// CreateApplet for slot 1 with a priority, disconnect policy and session
+fn create(priority: u8, on_disconnect: OrphanPolicy, session: bool) -> Command {
+    let mut command = Command::create(1, Separator::Empty);
+    command.priority = priority;
+    command.on_disconnect = on_disconnect;
+    command.session = session;
+    command
+}
+
+/// Session token of a reply
+fn token(reply: Reply) -> u64 {
+    match reply {
+        Reply::Response(x) => x.session.unwrap(),
+        x => panic!("expected a session token, got {x:?}"),
+    }
+}
+
+#[test]
+fn covered_applet_disconnects() {
+    let mut options = options();
+    options.preemption = Preemption::Cover;
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut low = stream();
+    let mut high = stream();
+    let command = create(0, OrphanPolicy::Clear, true);
+    let (reply, _) = run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let session = token(reply);
+    let command = create(5, OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut high,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+
+    // The covered applet is kept beneath for its session
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &low.owned,
+        low.id,
+        options.session_grace,
+    );
+    let shown = applets[0][1].as_mut().unwrap();
+    assert_eq!(shown.owner(), high.id);
+    assert!(shown
+        .find_owned(low.id)
+        .is_some_and(|(x, _)| x.is_orphaned()));
+
+    // And resumed where it is
+    let mut resumed = stream();
+    let command = Command::new(Opcode::Resume, 1, session.to_be_bytes().to_vec());
+    let (reply, _) = run_command(
+        command,
+        &mut resumed,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 2);
+    assert_eq!(resumed.owned, vec![(0, 1)]);
+    let command = Command::new(Opcode::UpdateGrid, 1, vec![1; 90]);
+    let (reply, _) = run_command(
+        command,
+        &mut resumed,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 2);
+
+    // The covering applet has no session, so its applet is shown again
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &high.owned,
+        high.id,
+        options.session_grace,
+    );
+    let shown = applets[0][1].as_ref().unwrap();
+    assert_eq!(shown.owner(), resumed.id);
+    assert!(!shown.is_orphaned());
+}
+
+#[test]
+fn covering_applet_disconnects() {
+    let mut options = options();
+    options.preemption = Preemption::Cover;
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut low = stream();
+    let mut high = stream();
+    let command = create(0, OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = create(5, OrphanPolicy::Keep(60), true);
+    let (reply, _) = run_command(
+        command,
+        &mut high,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let session = token(reply);
+
+    // The covering applet's policy keeps it on top
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &high.owned,
+        high.id,
+        options.session_grace,
+    );
+    let shown = applets[0][1].as_ref().unwrap();
+    assert!(shown.is_orphaned() && shown.owner() == high.id);
+    let mut resumed = stream();
+    let command = Command::new(Opcode::Resume, 1, session.to_be_bytes().to_vec());
+    let (reply, _) = run_command(
+        command,
+        &mut resumed,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 0);
+
+    // Once it expires the covered applet is shown again
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &resumed.owned,
+        resumed.id,
+        options.session_grace,
+    );
+    expire_orphans(
+        &mut applets,
+        &mut carousels,
+        Instant::now() + Duration::from_secs(61),
+    );
+    assert_eq!(applets[0][1].as_ref().unwrap().owner(), low.id);
+}
+
+#[test]
+fn preempted_applet_returns() {
+    let mut options = options();
+    options.preemption = Preemption::Preempt;
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut low = stream();
+    let mut high = stream();
+    let command = create(0, OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = create(5, OrphanPolicy::Clear, false);
+    let (reply, _) = run_command(
+        command,
+        &mut high,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 0);
+
+    // The preempted applet's stream is told and its commands fail
+    let notices = take_notices(&mut applets, &mut carousels, &low, &options.screens);
+    assert_eq!(notices.len(), 1);
+    assert_eq!((notices[0].code, notices[0].app_num), (37, Some(1)));
+    assert!(notices[0].notice);
+    let command = Command::new(Opcode::UpdateGrid, 1, vec![1; 90]);
+    let (reply, close) = run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 37);
+    assert!(!close);
+    assert_eq!(low.owned, vec![(0, 1)]);
+    assert!(take_notices(&mut applets, &mut carousels, &low, &options.screens).is_empty());
+
+    // Until the preempting applet is removed and it is shown again
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &high.owned,
+        high.id,
+        options.session_grace,
+    );
+    assert_eq!(applets[0][1].as_ref().unwrap().owner(), low.id);
+    let notices = take_notices(&mut applets, &mut carousels, &low, &options.screens);
+    assert_eq!(notices.len(), 1);
+    assert_eq!((notices[0].code, notices[0].app_num), (38, Some(1)));
+    let command = Command::new(Opcode::UpdateGrid, 1, vec![1; 90]);
+    let (reply, _) = run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 0);
+
+    // Notices are marked so clients can tell them from responses
+    let json = reply_bytes(&Reply::Response(notices[0].clone()), ResponseFormat::Json);
+    let sent: Response = serde_json::from_slice(&json).unwrap();
+    assert!(sent.notice);
+}
+
+#[test]
+fn covered_applet_expires() {
+    let mut options = options();
+    options.preemption = Preemption::Cover;
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut low = stream();
+    let mut high = stream();
+    let command = create(0, OrphanPolicy::Keep(10), false);
+    run_command(
+        command,
+        &mut low,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    let command = create(5, OrphanPolicy::Clear, false);
+    run_command(
+        command,
+        &mut high,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &low.owned,
+        low.id,
+        options.session_grace,
+    );
+    expire_orphans(
+        &mut applets,
+        &mut carousels,
+        Instant::now() + Duration::from_secs(5),
+    );
+    assert!(applets[0][1].as_mut().unwrap().find_owned(low.id).is_some());
+    expire_orphans(
+        &mut applets,
+        &mut carousels,
+        Instant::now() + Duration::from_secs(11),
+    );
+    assert!(applets[0][1].as_mut().unwrap().find_owned(low.id).is_none());
+    assert_eq!(applets[0][1].as_ref().unwrap().owner(), high.id);
+}
+
+#[test]
+fn orphan_fades_out() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    for command in [
+        create(0, OrphanPolicy::Fade(10), true),
+        Command::new(Opcode::UpdateGrid, 1, vec![200; 90]),
+    ] {
+        run_command(
+            command,
+            &mut stream,
+            &mut applets,
+            &mut carousels,
+            &options,
+            &[true],
+        );
+    }
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &stream.owned,
+        stream.id,
+        options.session_grace,
+    );
+    let start = applets[0][1].as_ref().unwrap().orphaned_at().unwrap();
+    let render = |applets: &[[Option<BoardApplet>; 4]], seconds| {
+        let time = FrameTime {
+            frame: 0,
+            tick: 0,
+            now: start + Duration::from_secs(seconds),
+        };
+        let mut transitions = Transitions::new(Transition::None, 15);
+        let screens = [vec![0]];
+        render_screens(
+            applets,
+            &screens,
+            Orientation::Normal,
+            &mut transitions,
+            None,
+            time,
+        )
+        .remove(0)
+        .pixels
+    };
+
+    // The grid is at half brightness half way through the fade
+    let half = render(&applets, 5);
+    assert!(half.contains(&100));
+    assert!(half.iter().all(|x| [0, 100].contains(x)));
+
+    // The frame is blank once the fade ends, though the applet can still be
+    // resumed until the session grace period is over
+    expire_orphans(
+        &mut applets,
+        &mut carousels,
+        start + Duration::from_secs(10),
+    );
+    assert!(applets[0][1].is_some());
+    assert!(render(&applets, 10).iter().all(|x| *x == 0));
+    let expires = applets[0][1].as_ref().unwrap().expires().unwrap();
+    assert!(expires >= start + Duration::from_secs(30));
+    expire_orphans(&mut applets, &mut carousels, expires);
+    assert!(applets[0][1].is_none());
+}
+
+#[test]
+fn set_separator_style() {
+    let options = options();
+    let mut applets = applets();
+    let mut carousels = Carousels::default();
+    let mut stream = stream();
+    let command = Command::create(1, Separator::Empty);
+    run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+
+    let command = Command::new(Opcode::SetSeparatorStyle, 1, vec![1]);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 0);
+    assert!(!close);
+    assert_eq!(
+        applets[0][1].as_ref().unwrap().applet.get_board(0)[0],
+        [255; 9]
+    );
+
+    // Invalid styles fail without closing the stream
+    let command = Command::new(Opcode::SetSeparatorStyle, 1, vec![9]);
+    let (reply, close) = run_command(
+        command,
+        &mut stream,
+        &mut applets,
+        &mut carousels,
+        &options,
+        &[true],
+    );
+    assert_eq!(reply.code(), 33);
+    assert!(!close);
+    assert_eq!(
+        applets[0][1].as_ref().unwrap().applet.get_board(0)[0],
+        [255; 9]
+    );
+}
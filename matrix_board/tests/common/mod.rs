@@ -0,0 +1,33 @@
+// Written by sigroot
+//! Fixtures shared by the tests of streams and the applet table
+
+// Each test file uses only some of them
+#![allow(dead_code)]
+
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::carousel::Carousels;
+use sig_rp2040_matrix_board::{Options, StreamState};
+use std::net::SocketAddr;
+
+/// Options of a board with one matrix
+pub fn options() -> Options {
+    Options {
+        screens: vec![vec![0]],
+        ..Options::default()
+    }
+}
+
+/// Empty applet table of one matrix
+pub fn applets() -> Vec<[Option<BoardApplet>; 4]> {
+    vec![[None, None, None, None]]
+}
+
+/// Applet table of one matrix and its carousels
+pub fn board() -> (Vec<[Option<BoardApplet>; 4]>, Carousels) {
+    (applets(), Carousels::default())
+}
+
+/// State of a newly connected stream
+pub fn stream() -> StreamState {
+    StreamState::new(SocketAddr::from(([127, 0, 0, 1], 0)))
+}
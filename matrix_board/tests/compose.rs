@@ -0,0 +1,130 @@
+// Written by sigroot
+//! Tests combining applet regions into whole matrices and screens
+
+use sig_rp2040_applet::{Applet, Command, Opcode, Orientation, Separator, Transition};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::transition::Transitions;
+use sig_rp2040_matrix_board::{
+    compose_regions, compose_screens, group_screens, parse_device, Canvas, FrameTime, Layout,
+};
+use std::time::Instant;
+
+/// Regions whose every row holds 16 times the region number plus the row
+/// (e.g. 35 is row 3 of region 2)
+fn regions() -> [[[u8; 9]; 11]; 4] {
+    let mut regions = [[[0; 9]; 11]; 4];
+    for (i, region) in regions.iter_mut().enumerate() {
+        for (j, row) in region.iter_mut().enumerate() {
+            *row = [(i * 16 + j) as u8; 9];
+        }
+    }
+    regions
+}
+
+#[test]
+fn compose_normal() {
+    let matrix = compose_regions(&regions(), Orientation::Normal);
+    // Only the status bar's separator is shown, then each grid region in turn
+    assert_eq!(matrix[0], [0; 9]);
+    assert_eq!(matrix[1], [16; 9]);
+    assert_eq!(matrix[11], [26; 9]);
+    assert_eq!(matrix[12], [32; 9]);
+    assert_eq!(matrix[23], [48; 9]);
+    assert_eq!(matrix[33], [58; 9]);
+}
+
+#[test]
+fn compose_oriented() {
+    let mut regions = regions();
+    regions[1][1][0] = 255;
+    let normal = compose_regions(&regions, Orientation::Normal);
+    assert_eq!(normal[2][0], 255);
+
+    // The whole matrix is transformed, not each region
+    let rotated = compose_regions(&regions, Orientation::Rotate180);
+    assert_eq!(rotated[31][8], 255);
+    assert_eq!(rotated[0], [58; 9]);
+    assert_eq!(rotated, Orientation::Rotate180.apply(&normal));
+    let flipped = compose_regions(&regions, Orientation::MirrorVertical);
+    assert_eq!(flipped[0], normal[33]);
+    assert_eq!(flipped[31][0], 255);
+}
+
+#[test]
+fn compose_applets() {
+    let mut applet = Applet::new(Separator::Solid);
+    applet
+        .command_applet(&Command::new(Opcode::UpdateGrid, 1, vec![7; 90]))
+        .unwrap();
+    let applets = [[
+        None,
+        Some(BoardApplet::new(applet.clone(), 1)),
+        None,
+        Some(BoardApplet::new(applet.clone(), 2)),
+    ]];
+    let mut regions = [[[0; 9]; 11]; 4];
+    regions[1] = applet.get_board(0);
+    regions[3] = applet.get_board(0);
+
+    // Applets are drawn as their regions and empty slots stay off
+    let mut transitions = Transitions::new(Transition::None, 15);
+    let time = FrameTime {
+        frame: 0,
+        tick: 0,
+        now: Instant::now(),
+    };
+    let screens = compose_screens(
+        &applets,
+        &[vec![0]],
+        Orientation::Rotate180,
+        &mut transitions,
+        time,
+    );
+    let matrix = screens[0].matrices()[0];
+    assert_eq!(matrix, compose_regions(&regions, Orientation::Rotate180));
+    assert_eq!(matrix[0], [7; 9]);
+    assert_eq!(matrix[33], [0; 9]);
+}
+
+#[test]
+fn layouts() {
+    assert_eq!(
+        parse_device("/dev/ttyACM0,canvas"),
+        Some(("/dev/ttyACM0".to_string(), Some(Layout::Canvas)))
+    );
+    assert_eq!(parse_device("ABC123"), Some(("ABC123".to_string(), None)));
+    assert_eq!(parse_device("/dev/ttyACM0,wide"), None);
+
+    // Canvas matrices form one screen where the first of them is
+    use Layout::*;
+    assert_eq!(group_screens(&[Screens, Screens]), [vec![0], vec![1]]);
+    assert_eq!(group_screens(&[Canvas, Canvas]), [vec![0, 1]]);
+    assert_eq!(
+        group_screens(&[Screens, Canvas, Screens, Canvas]),
+        [vec![0], vec![1, 3], vec![2]]
+    );
+}
+
+#[test]
+fn canvas() {
+    let mut left = [[0; 9]; 34];
+    let mut right = [[0; 9]; 34];
+    left[0][0] = 1;
+    right[33][8] = 2;
+    let canvas = Canvas::new(&[left, right], Orientation::Normal);
+    assert_eq!(canvas.width, 18);
+    assert_eq!(canvas.pixels[0], 1);
+    assert_eq!(canvas.pixels[33 * 18 + 17], 2);
+    assert_eq!(canvas.matrices(), [left, right]);
+
+    // The whole 18x34 canvas is oriented, moving pixels between matrices
+    let rotated = Canvas::new(&[left, right], Orientation::Rotate180).matrices();
+    assert_eq!(rotated[0][0][0], 2);
+    assert_eq!(rotated[1][33][8], 1);
+    let mirrored = Canvas::new(&[left, right], Orientation::MirrorHorizontal).matrices();
+    assert_eq!(mirrored[1][0][8], 1);
+    assert_eq!(mirrored[0][33][0], 2);
+    let flipped = Canvas::new(&[left, right], Orientation::MirrorVertical).matrices();
+    assert_eq!(flipped[0][33][0], 1);
+    assert_eq!(flipped[1][0][8], 2);
+}
@@ -0,0 +1,81 @@
+// Written by sigroot
+//! Tests finding LED matrices among the serial ports of a fake sysfs
+
+use sig_rp2040_matrix_board::device::{
+    find_matrix, find_port, list_devices, resolve_device, Backoff, FRAMEWORK_VID, LED_MATRIX_PID,
+    RECONNECT_MAX, RECONNECT_MIN,
+};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Sysfs of the fixtures (an LED matrix, a modem and a non-USB port)
+fn sys() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sys")
+}
+
+#[test]
+fn list() {
+    let devices = list_devices(&sys());
+    // Only USB serial ports are listed, in order
+    let ports: Vec<&str> = devices.iter().map(|x| x.port.as_str()).collect();
+    assert_eq!(ports, ["/dev/ttyACM0", "/dev/ttyACM1"]);
+
+    // USB attributes are read from the port's USB device
+    let matrix = &devices[0];
+    assert_eq!(matrix.vid, Some(FRAMEWORK_VID));
+    assert_eq!(matrix.pid, Some(LED_MATRIX_PID));
+    assert_eq!(matrix.serial.as_deref(), Some("FRAKDEAM0"));
+    assert_eq!(matrix.product.as_deref(), Some("LED Matrix Input Module"));
+    assert!(matrix.is_matrix());
+    assert_eq!(devices[1].vid, Some(0x1234));
+    assert!(!devices[1].is_matrix());
+}
+
+#[test]
+fn find() {
+    let sys = sys();
+    assert_eq!(find_matrix(&sys).unwrap(), "/dev/ttyACM0");
+    assert_eq!(
+        resolve_device("modem0", &sys).as_deref(),
+        Some("/dev/ttyACM1")
+    );
+    assert_eq!(resolve_device("missing", &sys), None);
+    assert_eq!(
+        find_port(Some("missing"), &sys).unwrap_err(),
+        "Matrix device not found: missing"
+    );
+
+    // Without sysfs there is nothing to find
+    let empty = Path::new("/nonexistent");
+    assert!(list_devices(empty).is_empty());
+    assert!(find_matrix(empty).is_err());
+    assert!(find_port(None, empty).is_err());
+}
+
+#[test]
+fn backoff() {
+    let start = Instant::now();
+    let mut backoff = Backoff::default();
+    backoff.reset(start);
+    assert!(!backoff.due(start + RECONNECT_MIN - Duration::from_millis(1)));
+    assert!(backoff.due(start + RECONNECT_MIN));
+
+    // Each failed attempt doubles the wait from when it failed
+    let mut now = start + RECONNECT_MIN;
+    for delay in [1, 2, 4, 8, 16] {
+        backoff.failed(now);
+        assert_eq!(backoff.delay, Duration::from_secs(delay));
+        assert!(!backoff.due(now + backoff.delay - Duration::from_millis(1)));
+        now += backoff.delay;
+        assert!(backoff.due(now));
+    }
+    // Until it reaches the longest wait
+    backoff.failed(now);
+    assert_eq!(backoff.delay, RECONNECT_MAX);
+    backoff.failed(now);
+    assert_eq!(backoff.next, now + RECONNECT_MAX);
+
+    // A new disconnect starts from the shortest wait again
+    backoff.reset(now);
+    assert_eq!(backoff.next, now + RECONNECT_MIN);
+}
@@ -0,0 +1,95 @@
+// Written by sigroot
+//! Tests dithering whole frames before they are written
+
+use sig_rp2040_applet::image::Dither;
+use sig_rp2040_applet::{Applet, Orientation, Separator, Transition};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::transition::Transitions;
+use sig_rp2040_matrix_board::{compose_screens, parse_dither, render_screens, FrameTime};
+use std::time::Instant;
+
+#[test]
+fn parse() {
+    assert_eq!(parse_dither("bayer"), Some((Dither::Bayer, 4)));
+    assert_eq!(parse_dither("temporal:2"), Some((Dither::Temporal, 2)));
+    assert_eq!(
+        parse_dither("floyd-steinberg:16"),
+        Some((Dither::FloydSteinberg, 16))
+    );
+    assert_eq!(parse_dither("none:1"), None);
+    assert_eq!(parse_dither("noise"), None);
+}
+
+/// Renders a status bar with a gradient separator (alone on one matrix) as a
+/// frame reduced with a dither
+fn gradient_frame(reduce: Option<(Dither, u8)>, frame: u64) -> Vec<u8> {
+    let gradient = BoardApplet::new(Applet::new(Separator::Gradient), 1);
+    let applets = vec![[Some(gradient), None, None, None]];
+    let mut transitions = Transitions::new(Transition::None, 15);
+    let time = FrameTime {
+        frame,
+        tick: 0,
+        now: Instant::now(),
+    };
+    render_screens(
+        &applets,
+        &[vec![0]],
+        Orientation::Normal,
+        &mut transitions,
+        reduce,
+        time,
+    )
+    .remove(0)
+    .pixels
+}
+
+#[test]
+fn gradient_separator() {
+    // Rounding keeps the gradient's ends and steps up through 3 levels
+    let rounded = gradient_frame(Some((Dither::None, 3)), 0);
+    assert_eq!(rounded[..9], [0, 0, 128, 128, 128, 128, 128, 255, 255]);
+
+    // Temporal dithering changes between frames but every value stays a level
+    let first = gradient_frame(Some((Dither::Temporal, 3)), 0);
+    let second = gradient_frame(Some((Dither::Temporal, 3)), 1);
+    assert_ne!(first[..9], second[..9]);
+    assert!(first.iter().all(|x| [0, 128, 255].contains(x)));
+}
+
+#[test]
+fn frames_without_dither() {
+    let gradient = BoardApplet::new(Applet::new(Separator::Gradient), 1);
+    let applets = vec![[Some(gradient), None, None, None]];
+    let screens = vec![vec![0]];
+    let time = FrameTime {
+        frame: 0,
+        tick: 0,
+        now: Instant::now(),
+    };
+    let render = |reduce| {
+        let mut transitions = Transitions::new(Transition::None, 15);
+        render_screens(
+            &applets,
+            &screens,
+            Orientation::Normal,
+            &mut transitions,
+            reduce,
+            time,
+        )
+    };
+    let mut transitions = Transitions::new(Transition::None, 15);
+    let composed = compose_screens(
+        &applets,
+        &screens,
+        Orientation::Normal,
+        &mut transitions,
+        time,
+    );
+
+    // Every brightness of the gradient is kept unless a dither is set
+    let frame = render(None);
+    assert_eq!(frame[0].pixels, composed[0].pixels);
+    let reduced = render(Some((Dither::None, 3)));
+    assert_ne!(reduced[0].pixels, composed[0].pixels);
+    assert!(reduced[0].pixels.iter().all(|x| [0, 128, 255].contains(x)));
+}
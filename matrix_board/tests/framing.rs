@@ -0,0 +1,57 @@
+// Written by sigroot
+//! Tests splitting stream data into JSON commands
+
+use serde_json::json;
+use sig_rp2040_matrix_board::take_values;
+
+/// Takes every complete value, panicking on invalid JSON
+fn take(read_data: &mut Vec<u8>) -> Vec<serde_json::Value> {
+    take_values(read_data)
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect()
+}
+
+#[test]
+fn pipelined_and_split_commands() {
+    let mut read_data = br#"{"opcode":"UpdateBar","parameters":[1]}{"opcode":"Update"#.to_vec();
+    assert_eq!(
+        take(&mut read_data),
+        [json!({"opcode": "UpdateBar", "parameters": [1]})]
+    );
+    assert_eq!(read_data, br#"{"opcode":"Update"#);
+
+    // The rest of a split command completes it
+    read_data.extend_from_slice(br#"Grid","parameters":[],"on_disconnect":{"Keep":5}}"#);
+    assert_eq!(
+        take(&mut read_data),
+        [json!({"opcode": "UpdateGrid", "parameters": [], "on_disconnect": {"Keep": 5}})]
+    );
+    assert!(read_data.is_empty());
+}
+
+#[test]
+fn braces_in_strings() {
+    let mut read_data = br#"{"opcode":"UpdateBar","slot":"}{"} {"slot":"\"{"}"#.to_vec();
+    assert_eq!(
+        take(&mut read_data),
+        [
+            json!({"opcode": "UpdateBar", "slot": "}{"}),
+            json!({"slot": "\"{"})
+        ]
+    );
+    let mut read_data = br#"{"slot":"}"#.to_vec();
+    assert!(take(&mut read_data).is_empty());
+    assert_eq!(read_data, br#"{"slot":"}"#);
+}
+
+#[test]
+fn invalid_json() {
+    let mut read_data = br#"{"opcode":"UpdateBar"} {"opcode" 1} {"opcode":"Hello"}"#.to_vec();
+    let values = take_values(&mut read_data);
+    assert_eq!(values.len(), 2);
+    assert!(values[0].is_ok());
+    assert!(values[1].is_err());
+    // The rest of the data can not be trusted and is dropped
+    assert!(read_data.is_empty());
+}
@@ -0,0 +1,30 @@
+// Written by sigroot
+//! Tests the image subcommand's arguments
+
+use sig_rp2040_matrix_board::image::parse_arguments;
+
+/// Converts string slices to owned arguments
+fn args(values: &[&str]) -> Vec<String> {
+    values.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn tone_flags() {
+    assert!(parse_arguments(&args(&["-t", "128", "a.png"])).is_ok());
+    assert!(parse_arguments(&args(&["-d", "bayer", "-d", "temporal", "a.png"])).is_ok());
+    // A threshold and a dither both set how brightnesses are reduced
+    let both = parse_arguments(&args(&["-t", "128", "-d", "bayer", "a.png"]));
+    assert_eq!(both.err().as_deref(), Some("Can not use -d with -t"));
+    assert!(parse_arguments(&args(&["-d", "bayer", "-t", "128", "a.png"])).is_err());
+}
+
+#[test]
+fn client_flags() {
+    assert!(
+        parse_arguments(&args(&["-p", "27100", "-k", "30", "a.png"]))
+            .unwrap()
+            .is_some()
+    );
+    assert!(parse_arguments(&args(&["-a", "256", "a.png"])).is_err());
+    assert!(parse_arguments(&args(&["-h"])).unwrap().is_none());
+}
@@ -0,0 +1,50 @@
+// Written by sigroot
+//! Tests detecting a system resume from the wall clock jumping ahead and
+//! watching logind without gdbus
+
+use sig_rp2040_matrix_board::resume::{watch_logind, ResumeDetector, RESUME_THRESHOLD};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Detector whose last readings are the returned clock readings
+fn detector() -> (ResumeDetector, Instant, SystemTime) {
+    let mut detector = ResumeDetector::new(RESUME_THRESHOLD);
+    let (instant, wall) = (Instant::now(), SystemTime::now());
+    detector.check_at(instant, wall);
+    (detector, instant, wall)
+}
+
+#[test]
+fn clocks_advance_together() {
+    let (mut detector, instant, wall) = detector();
+    let second = Duration::from_secs(1);
+    assert!(!detector.check_at(instant + second, wall + second));
+    // Small drift between the clocks is not a resume
+    assert!(!detector.check_at(instant + second * 2, wall + second * 3));
+    // Nor is the wall clock being set back
+    assert!(!detector.check_at(instant + second * 3, wall - second * 60));
+}
+
+#[test]
+fn clock_jump() {
+    let (mut detector, instant, wall) = detector();
+    // The monotonic clock stops while suspended, the wall clock does not
+    let suspended = Duration::from_secs(60);
+    let second = Duration::from_secs(1);
+    assert!(detector.check_at(instant + second, wall + second + suspended));
+    // Only the check right after the resume detects it
+    assert!(!detector.check_at(instant + second * 2, wall + second * 2 + suspended));
+}
+
+#[test]
+fn missing_gdbus() {
+    // Watching ends at once, leaving resume to the clock
+    let resumed = Arc::new(AtomicBool::new(false));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(watch_logind("/nonexistent/gdbus", Arc::clone(&resumed)));
+    assert!(!resumed.load(Ordering::SeqCst));
+}
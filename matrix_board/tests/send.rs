@@ -0,0 +1,91 @@
+// Written by sigroot
+//! Tests parsing the send subcommand's arguments and values
+
+use sig_rp2040_applet::{OrphanPolicy, Separator};
+use sig_rp2040_matrix_board::send::{parse_arguments, parse_values};
+
+/// Converts string slices to owned arguments
+fn args(values: &[&str]) -> Vec<String> {
+    values.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn defaults() {
+    let options = parse_arguments(&[]).unwrap().unwrap();
+    assert_eq!(options.client.port, 27072);
+    assert_eq!(options.client.app_num, 1);
+    assert_eq!(options.client.screen, 0);
+    assert_eq!(options.separator, Separator::Empty);
+    assert_eq!(options.client.on_disconnect, OrphanPolicy::KeepForever);
+    assert!(options.grid.is_none() && options.bar.is_none());
+}
+
+#[test]
+fn flags() {
+    let grid = vec!["7"; 90].join(",");
+    let options = parse_arguments(&args(&[
+        "-p",
+        "27100",
+        "-a",
+        "2",
+        "-s",
+        "1",
+        "-k",
+        "30",
+        "-g",
+        &grid,
+        "-b",
+        "1,2,3,4,5,6,7,8,9",
+    ]))
+    .unwrap()
+    .unwrap();
+    assert_eq!(options.client.port, 27100);
+    assert_eq!(options.client.app_num, 2);
+    assert_eq!(options.client.screen, 1);
+    assert_eq!(options.client.on_disconnect, OrphanPolicy::Keep(30));
+    assert_eq!(options.grid, Some(vec![7; 90]));
+    // A bar without a separator defaults to a variable separator
+    assert_eq!(options.separator, Separator::Variable);
+    assert_eq!(options.bar, Some((1..=9).collect()));
+}
+
+#[test]
+fn progress_bar() {
+    let options = parse_arguments(&args(&["-e", "7", "-b", "60"]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(options.separator, Separator::Progress);
+    assert_eq!(options.bar, Some(vec![60]));
+    // The separator may come after the bar
+    let options = parse_arguments(&args(&["-b", "60", "-e", "7"]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(options.bar, Some(vec![60]));
+    assert!(parse_arguments(&args(&["-e", "7", "-b", "1,2"])).is_err());
+}
+
+#[test]
+fn help_and_invalid() {
+    assert!(parse_arguments(&args(&["-h"])).unwrap().is_none());
+    assert!(parse_arguments(&args(&["-p"])).unwrap().is_none());
+    assert!(parse_arguments(&args(&["-x", "1"])).unwrap().is_none());
+    assert!(parse_arguments(&args(&["-p", "port"])).is_err());
+    assert!(parse_arguments(&args(&["-a", "256"])).is_err());
+    assert!(parse_arguments(&args(&["-k", "-1"])).is_err());
+    assert!(parse_arguments(&args(&["-e", "9"])).is_err());
+    let error = |x: &[&str]| parse_arguments(&args(x)).err().unwrap();
+    assert!(error(&["-g", "1,2,3"]).contains("expected 90 values"));
+    // Arguments that are not values are paths
+    assert!(error(&["-g", "missing.pgm"]).contains("file not found: missing.pgm"));
+}
+
+#[test]
+fn values() {
+    assert_eq!(parse_values("1,2, 3\n4\t5", 5), Ok(vec![1, 2, 3, 4, 5]));
+    assert_eq!(parse_values(",1,,2,", 2), Ok(vec![1, 2]));
+    assert!(parse_values("1,2", 3).is_err());
+    assert!(parse_values("1,2,3,4", 3).is_err());
+    assert!(parse_values("1,256", 2).is_err());
+    assert!(parse_values("1,x", 2).is_err());
+    assert_eq!(parse_values("", 0), Ok(vec![]));
+}
@@ -0,0 +1,85 @@
+// Written by sigroot
+//! Tests saving applets to a state file and restoring them
+
+use sig_rp2040_applet::{Applet, Command, Opcode, OrphanPolicy, Separator};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::carousel::Carousels;
+use sig_rp2040_matrix_board::replaceable;
+use sig_rp2040_matrix_board::state::{load, save, snapshot};
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+#[test]
+fn round_trip() {
+    let mut applet = Applet::new(Separator::Variable);
+    let grid: Vec<u8> = (0..90).collect();
+    applet
+        .command_applet(&Command::new(Opcode::UpdateGrid, 1, grid))
+        .unwrap();
+    applet.set_session(Some(0x0102030405060708));
+    applet.set_on_disconnect(OrphanPolicy::KeepForever);
+    let applets = vec![[None, Some(BoardApplet::new(applet.clone(), 1)), None, None]];
+
+    let path = env::temp_dir().join(format!("sig_rp2040_state_{}.json", std::process::id()));
+    save(&path, &snapshot(&applets, &Carousels::default()).unwrap()).unwrap();
+    let restored = load(&path, 2);
+    fs::remove_file(&path).unwrap();
+    let (restored, _) = restored.unwrap();
+
+    // Slots outside the file are empty, the applet keeps its content and token
+    assert_eq!(restored.len(), 2);
+    assert!(restored[1].iter().all(|x| x.is_none()));
+    let slot = &restored[0][1];
+    let x = slot.as_ref().unwrap();
+    assert_eq!(x.applet.get_board(0), applet.get_board(0));
+    assert_eq!(x.applet.session(), applet.session());
+    assert_eq!(x.applet.on_disconnect(), OrphanPolicy::KeepForever);
+
+    // Restored applets wait for their owner for the grace period
+    assert!(x.is_orphaned() && x.is_restored());
+    assert!(!replaceable(slot, Duration::from_secs(30)));
+    assert!(replaceable(slot, Duration::ZERO));
+}
+
+#[test]
+fn restored_without_session() {
+    let applets = vec![[
+        Some(BoardApplet::new(Applet::new(Separator::Solid), 1)),
+        None,
+        None,
+        None,
+    ]];
+    let path = env::temp_dir().join(format!("sig_rp2040_state_{}_2.json", std::process::id()));
+    save(&path, &snapshot(&applets, &Carousels::default()).unwrap()).unwrap();
+    let restored = load(&path, 1);
+    fs::remove_file(&path).unwrap();
+    let (mut restored, _) = restored.unwrap();
+    assert!(!replaceable(&restored[0][0], Duration::from_secs(30)));
+
+    // Resuming (or creating a new applet) ends the restored state
+    let x = restored[0][0].as_mut().unwrap();
+    x.set_orphaned(false);
+    assert!(!x.is_restored());
+}
+
+#[test]
+fn restored_ids() {
+    let applets = vec![[
+        None,
+        Some(BoardApplet::new(Applet::new(Separator::Solid), 1)),
+        Some(BoardApplet::new(Applet::new(Separator::Empty), 2)),
+        None,
+    ]];
+    let path = env::temp_dir().join(format!("sig_rp2040_state_{}_3.json", std::process::id()));
+    save(&path, &snapshot(&applets, &Carousels::default()).unwrap()).unwrap();
+    let restored = load(&path, 1);
+    fs::remove_file(&path).unwrap();
+    let (restored, _) = restored.unwrap();
+
+    // Each restored applet is told apart from the others (and from new ones)
+    let id = |x: &Option<BoardApplet>| x.as_ref().unwrap().id();
+    let new = BoardApplet::new(Applet::new(Separator::Solid), 3).id();
+    assert_ne!(id(&restored[0][1]), id(&restored[0][2]));
+    assert!(id(&restored[0][1]) != new && id(&restored[0][2]) != new);
+}
@@ -0,0 +1,170 @@
+// Written by sigroot
+//! Tests reading system statistics and status bar widgets from fixtures
+
+use sig_rp2040_matrix_board::sysmon::{draw_monitor, parse_monitor, Monitor, MonitorState};
+use sig_rp2040_matrix_board::system::{self, CpuTimes, Sources};
+use sig_rp2040_matrix_board::widgets::{widget_level, Widget};
+use std::path::{Path, PathBuf};
+
+/// Sources pointing at the fixtures directory
+fn fixtures() -> Sources {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    Sources {
+        proc: root.join("proc"),
+        sys: root.join("sys"),
+    }
+}
+
+/// Sources pointing at a later sample of the fixtures' statistics
+fn later() -> Sources {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    Sources {
+        proc: root.join("later/proc"),
+        sys: root.join("sys"),
+    }
+}
+
+/// Column of a grid, from the top
+fn column(grid: &[[u8; 9]; 10], column: usize) -> Vec<u8> {
+    grid.iter().map(|x| x[column]).collect()
+}
+
+#[test]
+fn cpu_times() {
+    let cpus = system::cpu_times(&fixtures().proc).unwrap();
+    assert_eq!(cpus.len(), 3);
+    assert_eq!(
+        cpus[0],
+        CpuTimes {
+            busy: 200,
+            total: 1000
+        }
+    );
+    let later = CpuTimes {
+        busy: 250,
+        total: 1100,
+    };
+    assert_eq!(later.load_since(&cpus[0]), 50);
+}
+
+#[test]
+fn widgets() {
+    let sources = fixtures();
+    let mut previous = None;
+    assert_eq!(
+        widget_level(Widget::Battery, &sources, &mut previous).unwrap(),
+        87
+    );
+    assert_eq!(
+        widget_level(Widget::Memory, &sources, &mut previous).unwrap(),
+        75
+    );
+    // CPU load needs two samples
+    assert_eq!(
+        widget_level(Widget::Cpu, &sources, &mut previous).unwrap(),
+        0
+    );
+    assert!(previous.is_some());
+}
+
+#[test]
+fn missing_sources() {
+    let sources = Sources {
+        proc: PathBuf::from("/nonexistent"),
+        sys: PathBuf::from("/nonexistent"),
+    };
+    assert!(widget_level(Widget::Battery, &sources, &mut None).is_err());
+    assert!(widget_level(Widget::Cpu, &sources, &mut None).is_err());
+}
+
+#[test]
+fn network_and_disk_counters() {
+    let sources = fixtures();
+    assert_eq!(system::network_bytes(&sources.proc).unwrap(), 1325000);
+    // Partitions and device mapper volumes are not counted again
+    assert_eq!(
+        system::disk_sectors(&sources.proc, &sources.sys).unwrap(),
+        60000
+    );
+    let sources = later();
+    assert_eq!(system::network_bytes(&sources.proc).unwrap(), 1975000);
+    assert_eq!(
+        system::disk_sectors(&sources.proc, &sources.sys).unwrap(),
+        76000
+    );
+}
+
+#[test]
+fn monitors() {
+    let sources = fixtures();
+    let mut state = MonitorState::default();
+    let grid = draw_monitor(Monitor::Memory, &sources, &mut state).unwrap();
+    // 75% fills 7 rows and half of the 8th
+    assert_eq!(grid[1], [0; 9]);
+    assert_eq!(grid[2], [127; 9]);
+    assert_eq!(grid[3..].concat(), vec![255; 63]);
+
+    // Counters are drawn once they have changed (fixtures never change)
+    let grid = draw_monitor(Monitor::Network, &sources, &mut state).unwrap();
+    assert_eq!(grid, [[0; 9]; 10]);
+    let grid = draw_monitor(Monitor::Disk, &sources, &mut MonitorState::default()).unwrap();
+    assert_eq!(grid, [[0; 9]; 10]);
+
+    assert!(parse_monitor("cpu").unwrap().1.allocates());
+    assert_eq!(parse_monitor("disk:3").unwrap().1.app_num, 3);
+    assert_eq!(
+        parse_monitor("network:top").unwrap().1.slot.as_deref(),
+        Some("top")
+    );
+    assert!(parse_monitor("gpu").is_none());
+}
+
+#[test]
+fn cpu_columns() {
+    let mut state = MonitorState::default();
+    let grid = draw_monitor(Monitor::Cpu, &fixtures(), &mut state).unwrap();
+    assert_eq!(grid, [[0; 9]; 10]);
+
+    // The first core (100% busy) fills columns 0-4 and the second (50% busy)
+    // half fills columns 5-8
+    let grid = draw_monitor(Monitor::Cpu, &later(), &mut state).unwrap();
+    for i in 0..5 {
+        assert_eq!(column(&grid, i), [255; 10]);
+    }
+    for i in 5..9 {
+        assert_eq!(column(&grid, i), [0, 0, 0, 0, 0, 255, 255, 255, 255, 255]);
+    }
+}
+
+#[test]
+fn sparklines() {
+    let (first, second) = (fixtures(), later());
+    let mut state = MonitorState::default();
+    let mut draw = |sources| draw_monitor(Monitor::Network, sources, &mut state).unwrap();
+    assert_eq!(draw(&first), [[0; 9]; 10]);
+
+    // The largest change fills a column whatever its size
+    let grid = draw(&second);
+    assert_eq!(column(&grid, 8), [255; 10]);
+    assert_eq!(
+        grid.iter().map(|x| x[..8].to_vec()).collect::<Vec<_>>(),
+        vec![vec![0; 8]; 10]
+    );
+
+    // Counters going backwards are drawn as no change and older changes
+    // move to the left
+    let grid = draw(&first);
+    assert_eq!(column(&grid, 7), [255; 10]);
+    assert_eq!(column(&grid, 8), [0; 10]);
+    let grid = draw(&second);
+    assert_eq!(column(&grid, 6), [255; 10]);
+    assert_eq!(column(&grid, 7), [0; 10]);
+    assert_eq!(column(&grid, 8), [255; 10]);
+
+    // 16000 sectors also fill a column as the largest change of the disk
+    let mut state = MonitorState::default();
+    draw_monitor(Monitor::Disk, &first, &mut state).unwrap();
+    let grid = draw_monitor(Monitor::Disk, &second, &mut state).unwrap();
+    assert_eq!(column(&grid, 8), [255; 10]);
+    assert_eq!(column(&grid, 7), [0; 10]);
+}
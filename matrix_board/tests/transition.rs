@@ -0,0 +1,80 @@
+// Written by sigroot
+//! Tests rendering transitions when the applet shown in a slot changes
+
+use sig_rp2040_applet::{Applet, Command, Opcode, Separator, Transition};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::transition::Transitions;
+use std::time::Instant;
+
+/// Applet with every grid LED at a brightness
+fn applet(brightness: u8) -> Applet {
+    let mut applet = Applet::new(Separator::Solid);
+    applet
+        .command_applet(&Command::new(Opcode::UpdateGrid, 1, vec![brightness; 90]))
+        .unwrap();
+    applet
+}
+
+#[test]
+fn board_default() {
+    let now = Instant::now();
+    let mut transitions = Transitions::new(Transition::WipeDown, 2);
+    let shown = applet(50);
+    let region = shown.get_board(0);
+    let applets = [None, Some(BoardApplet::new(shown, 1)), None, None];
+
+    // Half way through the applet has wiped down over the empty slot
+    let first = transitions.render(0, &applets, 0, now);
+    assert_eq!(first[1][..6], region[..6]);
+    assert_eq!(first[1][6..], [[0; 9]; 5]);
+    assert_eq!(first[2], [[0; 9]; 11]);
+    assert_eq!(transitions.render(0, &applets, 0, now)[1], region);
+    assert_eq!(transitions.render(0, &applets, 0, now)[1], region);
+
+    // Removing the applet wipes the empty slot back over it
+    let removed = transitions.render(0, &[None, None, None, None], 0, now);
+    assert_eq!(removed[1][..6], [[0; 9]; 6]);
+    assert_eq!(removed[1][6..], region[6..]);
+}
+
+#[test]
+fn applet_transition() {
+    let now = Instant::now();
+    let mut transitions = Transitions::new(Transition::None, 15);
+    let first = applet(50);
+    let from = first.get_board(0);
+    assert_eq!(
+        transitions.render(
+            1,
+            &[None, Some(BoardApplet::new(first, 1)), None, None],
+            0,
+            now
+        )[1],
+        from
+    );
+
+    // The next applet's effect and frames replace the board defaults
+    let mut next = applet(150);
+    next.set_transition(Some(Transition::Fade), 4);
+    let to = next.get_board(0);
+    let applets = [None, Some(BoardApplet::new(next, 1)), None, None];
+    for frame in 1..=4 {
+        let progress = frame as f32 / 4.0;
+        assert_eq!(
+            transitions.render(1, &applets, 0, now)[1].to_vec(),
+            Transition::Fade.apply(&from, &to, progress)
+        );
+    }
+    assert_eq!(transitions.render(1, &applets, 0, now)[1], to);
+
+    // The same content in a new applet is still a change
+    let mut same = applet(150);
+    same.set_transition(Some(Transition::Slide), 2);
+    let rendered = transitions.render(
+        1,
+        &[None, Some(BoardApplet::new(same, 1)), None, None],
+        0,
+        now,
+    );
+    assert_eq!(rendered[1].to_vec(), Transition::Slide.apply(&to, &to, 0.5));
+}
@@ -0,0 +1,201 @@
+// Written by sigroot
+//! Tests status bar widgets claiming and giving back the status bar
+
+mod common;
+
+use common::{board, options, stream};
+use sig_rp2040_applet::{Command, OrphanPolicy, Separator};
+use sig_rp2040_matrix_board::applet::BoardApplet;
+use sig_rp2040_matrix_board::carousel::Carousels;
+use sig_rp2040_matrix_board::state::snapshot;
+use sig_rp2040_matrix_board::widgets::{claim_bar, parse_widget, Widget};
+use sig_rp2040_matrix_board::{run_command, stop_applet, Options, Preemption, StreamState};
+use std::time::{Duration, Instant};
+
+/// Creates a status bar for a stream, returning the response code
+fn create_bar(
+    stream: &mut StreamState,
+    priority: u8,
+    on_disconnect: OrphanPolicy,
+    applets: &mut [[Option<BoardApplet>; 4]],
+    carousels: &mut Carousels,
+    options: &Options,
+) -> u8 {
+    let mut command = Command::create(0, Separator::Variable);
+    command.priority = priority;
+    command.on_disconnect = on_disconnect;
+    let (reply, _) = run_command(command, stream, applets, carousels, options, &[true]);
+    reply.code()
+}
+
+/// Owner of the applet shown on the status bar
+fn shown(applets: &[[Option<BoardApplet>; 4]]) -> Option<u64> {
+    applets[0][0].as_ref().map(|x| x.owner())
+}
+
+#[test]
+fn parse() {
+    assert_eq!(parse_widget("cpu"), Some((Widget::Cpu, 0)));
+    assert_eq!(parse_widget("seconds:1"), Some((Widget::Seconds, 1)));
+    assert_eq!(parse_widget("cpu:bar"), None);
+    assert_eq!(parse_widget("disk"), None);
+}
+
+#[test]
+fn streams_cover_widgets() {
+    let options = options();
+    let grace = options.session_grace;
+    let (mut applets, mut carousels) = board();
+    let widget = 1_000_001;
+    assert!(claim_bar(
+        &mut applets[0][0],
+        (0, 0),
+        &mut carousels,
+        widget,
+        grace
+    ));
+    assert_eq!(shown(&applets), Some(widget));
+    assert!(applets[0][0].as_ref().unwrap().is_lowest_priority());
+
+    // Any stream's status bar covers the widget (whatever the policy)
+    let mut stream = stream();
+    let code = create_bar(
+        &mut stream,
+        0,
+        OrphanPolicy::Clear,
+        &mut applets,
+        &mut carousels,
+        &options,
+    );
+    assert_eq!(code, 0);
+    assert_eq!(shown(&applets), Some(stream.id));
+    assert!(claim_bar(
+        &mut applets[0][0],
+        (0, 0),
+        &mut carousels,
+        widget,
+        grace
+    ));
+    assert_eq!(shown(&applets), Some(stream.id));
+
+    // The widget returns once the stream's applet is removed
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &stream.owned,
+        stream.id,
+        grace,
+    );
+    assert_eq!(shown(&applets), Some(widget));
+
+    // Releasing the bar leaves it empty
+    stop_applet(&mut applets, &mut carousels, &[(0, 0)], widget, grace);
+    assert!(applets[0][0].is_none());
+}
+
+#[test]
+fn orphans_kept_forever() {
+    let options = options();
+    let (mut applets, mut carousels) = board();
+    let mut stream = stream();
+    create_bar(
+        &mut stream,
+        0,
+        OrphanPolicy::KeepForever,
+        &mut applets,
+        &mut carousels,
+        &options,
+    );
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &stream.owned,
+        stream.id,
+        Duration::ZERO,
+    );
+    assert!(applets[0][0].as_ref().unwrap().is_orphaned());
+
+    // The orphan could be replaced by a stream, but not by a widget
+    let slot = &mut applets[0][0];
+    assert!(!claim_bar(
+        slot,
+        (0, 0),
+        &mut carousels,
+        1_000_002,
+        Duration::ZERO
+    ));
+    assert_eq!(shown(&applets), Some(stream.id));
+}
+
+#[test]
+fn covered_applets_stay() {
+    let mut options = options();
+    options.preemption = Preemption::Cover;
+    let (mut applets, mut carousels) = board();
+    let mut low = stream();
+    let mut high = stream();
+    create_bar(
+        &mut low,
+        0,
+        OrphanPolicy::Clear,
+        &mut applets,
+        &mut carousels,
+        &options,
+    );
+    create_bar(
+        &mut high,
+        1,
+        OrphanPolicy::Keep(60),
+        &mut applets,
+        &mut carousels,
+        &options,
+    );
+    stop_applet(
+        &mut applets,
+        &mut carousels,
+        &high.owned,
+        high.id,
+        options.session_grace,
+    );
+
+    // Replacing the orphan shows the applet it covered, not the widget
+    let slot = &mut applets[0][0];
+    let grace = options.session_grace;
+    assert!(!claim_bar(slot, (0, 0), &mut carousels, 1_000_003, grace));
+    assert_eq!(shown(&applets), Some(low.id));
+}
+
+#[test]
+fn widgets_take_turns() {
+    let options = options();
+    let grace = options.session_grace;
+    let (mut applets, mut carousels) = board();
+    let (first, second) = (1_000_004, 1_000_005);
+    assert!(claim_bar(
+        &mut applets[0][0],
+        (0, 0),
+        &mut carousels,
+        first,
+        grace
+    ));
+    assert!(claim_bar(
+        &mut applets[0][0],
+        (0, 0),
+        &mut carousels,
+        second,
+        grace
+    ));
+    assert_eq!(shown(&applets), Some(first));
+    assert_eq!(carousels.waiting((0, 0)), 1);
+    let turn = Instant::now() + options.dwell;
+    carousels.rotate(&mut applets, options.dwell, turn);
+    assert_eq!(shown(&applets), Some(second));
+
+    // Widgets are drawn by the board, so they are not saved
+    assert_eq!(snapshot(&applets, &carousels).unwrap(), "[]");
+
+    // A stopped widget gives its turn away
+    stop_applet(&mut applets, &mut carousels, &[(0, 0)], second, grace);
+    assert_eq!(shown(&applets), Some(first));
+    assert_eq!(carousels.waiting((0, 0)), 0);
+}